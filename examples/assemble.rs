@@ -0,0 +1,13 @@
+use railway::assembling::assemble;
+use std::env::args;
+use std::fs;
+
+fn main() {
+	let mut args = args().skip(1);
+	let (source, destination) = (args.next().unwrap(), args.next().unwrap());
+	let text = fs::read_to_string(&source).unwrap();
+	match assemble(&text) {
+		Ok(document) => fs::write(destination, document.serialize()).unwrap(),
+		Err(e) => eprintln!("{}: {}", source, e),
+	}
+}
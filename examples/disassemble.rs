@@ -0,0 +1,13 @@
+use railway::{SerializedProgram, assembling::disassemble};
+use std::env::args;
+use std::fs;
+
+fn main() {
+	let file_name = args().next_back().unwrap();
+	let railway = fs::read(&file_name).unwrap();
+	let program = SerializedProgram::new(railway).unwrap();
+	match disassemble(&program) {
+		Ok(text) => print!("{}", text),
+		Err(e) => eprintln!("{}: {}", file_name, e),
+	}
+}
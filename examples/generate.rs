@@ -1,88 +1,40 @@
-use railway::{*, computing::{*, Operation::*}};
+use railway::{building::*, computing::*};
 use std::env::args;
 use std::fs;
 
-fn arg(name: Option<String>, value: Couple) -> Argument<String> {
-	Argument {
-		name,
-		value,
-		range: (value, value),
-	}
-}
-
 use core::f32::consts::TAU;
 
 fn main() {
 	let w = 200.0;
 	let h = 200.0;
 
-	let mut arguments = Vec::new();
-
-	let _zero = arguments.len();
-	arguments.push(arg(None, C_ZERO));
-
-	let size = arguments.len();
-	arguments.push(arg(Some("size".into()), Couple::new(w, h)));
-
-	let top_left_f = arguments.len();
-	arguments.push(arg(None, Couple::new(0.05, 0.05)));
-
-	let bottom_right_f = arguments.len();
-	arguments.push(arg(None, Couple::new(0.95, 0.95)));
-
-	let contour_rg = arguments.len();
-	arguments.push(arg(None, Couple::new(0.5, 0.1)));
-
-	let contour_ba = arguments.len();
-	arguments.push(arg(None, Couple::new(0.5, 1.0)));
-
-	let pattern = arguments.len();
-	arguments.push(arg(None, Couple::new(100.0, 0.0)));
-
-	let width = arguments.len();
-	arguments.push(arg(None, Couple::new(4.0, 0.0)));
-
-	let inverted_rg = arguments.len();
-	arguments.push(arg(None, Couple::new(0.1, 0.5)));
-
-	let deltas = arguments.len();
-	arguments.push(arg(None, Couple::new(TAU, 0.0)));
-
-	let radius = arguments.len();
-	arguments.push(arg(None, Couple::new(0.0, -40.0)));
-
-	let center_f = arguments.len();
-	arguments.push(arg(None, Couple::new(0.75, 0.25)));
-
-	let mut instructions = Vec::new();
-
-	let top_left = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Multiply2, size, top_left_f, 0));
-
-	let bottom_right = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Multiply2, size, bottom_right_f, 0));
-
-	let bottom_left = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Select2, top_left, bottom_right, 0));
-
-	let top_right = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Select2, bottom_right, top_left, 0));
-
-	let center = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Multiply2, size, center_f, 0));
-
-	let start_point = arguments.len() + instructions.len();
-	instructions.push(Instruction::new(Add2, center, radius, 0));
+	let mut b = ProgramBuilder::new();
+
+	let _zero = b.constant(C_ZERO);
+	let size = b.arg("size", Couple::new(w, h));
+	let top_left_f = b.constant(Couple::new(0.05, 0.05));
+	let bottom_right_f = b.constant(Couple::new(0.95, 0.95));
+	let contour_rg = b.constant(Couple::new(0.5, 0.1));
+	let contour_ba = b.constant(Couple::new(0.5, 1.0));
+	let pattern = b.constant(Couple::new(100.0, 0.0));
+	let width = b.constant(Couple::new(4.0, 0.0));
+	let inverted_rg = b.constant(Couple::new(0.1, 0.5));
+	let deltas = b.constant(Couple::new(TAU, 0.0));
+	let radius = b.constant(Couple::new(0.0, -40.0));
+	let center_f = b.constant(Couple::new(0.75, 0.25));
+
+	let top_left = b.eval(size * top_left_f);
+	let bottom_right = b.eval(size * bottom_right_f);
+	let bottom_left = b.eval(top_left.select(bottom_right));
+	let top_right = b.eval(bottom_right.select(top_left));
+	let center = b.eval(size * center_f);
+	let start_point = b.eval(center + radius);
 
 	let contour = [contour_rg, contour_ba];
 
-	let line_style = Stroker {
-		pattern,
-		width,
-		color: contour,
-	};
+	let line_style = Stroker::new(pattern, width, contour);
 
-	let background = vec![
+	let background = [
 		Triangle {
 			points: [top_left, bottom_left, bottom_right],
 			colors: [contour, [inverted_rg, contour_ba], contour],
@@ -93,7 +45,7 @@ fn main() {
 		},
 	];
 
-	let slope = vec![
+	let slope = [
 		PathStep::Line(Line {
 			points: [bottom_left, top_left],
 		}),
@@ -105,20 +57,17 @@ fn main() {
 		}),
 	];
 
-	let disk = vec![PathStep::Arc(Arc {
+	let disk = [PathStep::Arc(Arc {
 		start_point,
 		center,
 		deltas,
 	})];
 
-	let rendering_steps = [
-		RenderingStep::Clip(&slope, &background),
-		RenderingStep::Stroke(&slope, line_style),
-		RenderingStep::Clip(&disk, &background),
-		RenderingStep::Stroke(&disk, line_style),
-	];
+	b.clip(&slope, &background, FillRule::NonZero);
+	b.stroke(&slope, line_style);
+	b.clip(&disk, &background, FillRule::NonZero);
+	b.stroke(&disk, line_style);
 
-	let buffer = serialize(&arguments, &instructions, &[], &rendering_steps);
-	let file_name = args().last().unwrap();
-	fs::write(file_name, &buffer).unwrap();
+	let file_name = args().nth(1).unwrap();
+	fs::write(file_name, b.serialize()).unwrap();
 }
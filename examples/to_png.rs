@@ -8,13 +8,14 @@ use std::time::Instant;
 use rgb::FromSlice;
 
 fn main() {
-	let prefix = args().last().unwrap();
+	let prefix = args().nth(1).unwrap();
 	let rwy_name = format!("{}.rwy", &prefix);
 	let png_name = format!("{}.png", &prefix);
 
 	let railway = std::fs::read(&rwy_name).unwrap();
 	let mut p = NaiveRenderer::parse(railway.into_boxed_slice()).unwrap();
-	let (w, h) = (300, 300);
+	let size = p.get_program().intrinsic_size().unwrap();
+	let (w, h) = size.map_or((300, 300), |s| (s.x as usize, s.y as usize));
 	p.set_argument("size", computing::Couple::new(w as f32, h as f32)).unwrap();
 	p.compute().unwrap();
 	let length = w * h;
@@ -0,0 +1,1265 @@
+//! Textual assembly format for railway programs
+//!
+//! Each statement takes one line; `#` starts a comment.
+//!
+//! ```text
+//! size = arg "size" (200.0, 200.0) range (0.0, 0.0) (1000.0, 1000.0)
+//! top_left_f = arg (0.05, 0.05)
+//! top_left = Multiply2 size, top_left_f
+//! output "top_left" top_left
+//!
+//! track size
+//!     key 0.0 (200.0, 200.0)
+//!     key 1.5 (400.0, 200.0) smooth
+//! end
+//!
+//! playback 1.5 pingpong fps 30.0
+//! meta "title" "Frame"
+//! meta "width" "200"
+//! meta "height" "200"
+//! meta "view-box" "0 0 200 200"
+//!
+//! path frame
+//!     line top_left, bottom_right
+//!     quadratic top_left, bottom_left, bottom_right
+//!     cubic a, b, c, d
+//!     arc start, center, deltas
+//!     contour
+//!     line a, b
+//! end
+//!
+//! background mesh
+//!     triangle a, b, c, [rg, ba], [rg, ba], [rg, ba]
+//! end
+//!
+//! gradient glow radial center, radius, focal
+//!     stop zero, [rg, ba]
+//!     stop one, [rg, ba]
+//! end
+//!
+//! stroker contour pattern, width, [rg, ba]
+//! stroker polyline pattern, width, [rg, ba] open cap square join miter miter 10.0
+//!
+//! clip frame, mesh
+//! clip ring, glow evenodd
+//! clip frame, [rg, ba] blend multiply
+//! stroke frame, contour
+//!
+//! group opacity blend screen
+//!     clip frame, mesh
+//!     stroke frame, contour
+//! end
+//!
+//! push ring evenodd
+//! clip frame, glow
+//! pop
+//! ```
+//!
+//! Gradients are `linear start, end`, `radial center, radius[, focal]`
+//! or `conic center, angle`; clips accept backgrounds, gradients and
+//! solid colors. Clips and strokes end with an optional blend mode,
+//! such as `blend screen` or `blend destination-out`. Groups draw the
+//! steps up to their `end` in a separate layer, which is then blended
+//! with the opacity at X of their operand. `push` restricts the steps
+//! up to the matching `pop` to the inside of a path, and of the paths
+//! pushed before it.
+//!
+//! Tracks animate arguments: each keyframe has a time in seconds, a
+//! value and how it changes until the next keyframe (`step`, `linear`
+//! by default, or `smooth`). The optional `playback` statement has the
+//! duration of the animation, then optionally `once` (the default),
+//! `loop` or `pingpong` and a frame rate (60 by default).
+//! `meta` statements add metadata entries.
+//!
+//! Strokers are closed, with round caps and joins and a miter limit
+//! of 4 unless stated otherwise; caps are `butt`, `round` or `square`
+//! and joins are `miter`, `round` or `bevel`.
+//!
+//! Labels are only used in the text: stack entries, paths, backgrounds,
+//! gradients and strokers are referenced by index in the binary format, so
+//! [`disassemble`] generates labels for everything but named arguments
+//! and outputs.
+
+use crate::computing::*;
+use crate::editing::Document;
+
+use alloc::{vec, vec::Vec, string::String, format};
+use alloc::collections::BTreeMap;
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssemblyErrorKind {
+    UnexpectedToken(String),
+    UnexpectedEndOfLine,
+    UnterminatedString,
+    UnclosedBlock,
+    InvalidNumber(String),
+    UnknownOperation(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    /// an instruction uses a stack entry which isn't computed before it
+    NotComputedYet(String),
+    /// a track animates a stack entry which isn't an argument
+    NotAnArgument(String),
+    /// clip `push` without a `pop` in the same group, or the reverse
+    UnbalancedClip,
+    MissingOperands,
+}
+
+/// An assembly error and the line where it was found
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssemblyError {
+    /// line number, starting at 1
+    pub line: usize,
+    pub kind: AssemblyErrorKind,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AssemblyErrorKind::*;
+
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            UnexpectedToken(t) => write!(f, "unexpected `{}`", t),
+            UnexpectedEndOfLine => write!(f, "unexpected end of line"),
+            UnterminatedString => write!(f, "unterminated string"),
+            UnclosedBlock => write!(f, "missing `end`"),
+            InvalidNumber(t) => write!(f, "invalid number `{}`", t),
+            UnknownOperation(t) => write!(f, "unknown operation `{}`", t),
+            UnknownLabel(t) => write!(f, "unknown label `{}`", t),
+            DuplicateLabel(t) => write!(f, "label `{}` is already defined", t),
+            NotComputedYet(t) => write!(f, "`{}` isn't computed before this instruction", t),
+            NotAnArgument(t) => write!(f, "`{}` isn't an argument", t),
+            UnbalancedClip => write!(f, "clip `push` and `pop` don't match in this group"),
+            MissingOperands => write!(f, "missing operands"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AssemblyError {}
+
+pub type AssemblyResult<T> = Result<T, AssemblyError>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token<'a> {
+    Word(&'a str),
+    Str(String),
+    Punct(char),
+}
+
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(w) => f.write_str(w),
+            Token::Str(s) => write_string(f, s),
+            Token::Punct(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+const PUNCTUATION: &str = "()[]=,";
+
+fn tokenize(line: &str) -> Result<Vec<Token<'_>>, AssemblyErrorKind> {
+    let mut tokens = Vec::new();
+    let mut rest = line;
+
+    loop {
+        rest = rest.trim_start();
+        let mut chars = rest.chars();
+        let Some(c) = chars.next() else { break };
+
+        if c == '#' {
+            break;
+        } else if PUNCTUATION.contains(c) {
+            tokens.push(Token::Punct(c));
+            rest = chars.as_str();
+        } else if c == '"' {
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => string.push(match chars.next() {
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some(c) => c,
+                        None => return Err(AssemblyErrorKind::UnterminatedString),
+                    }),
+                    Some(c) => string.push(c),
+                    None => return Err(AssemblyErrorKind::UnterminatedString),
+                }
+            }
+            tokens.push(Token::Str(string));
+            rest = chars.as_str();
+        } else {
+            let is_end = |c: char| c.is_whitespace() || PUNCTUATION.contains(c) || c == '"' || c == '#';
+            let len = rest.find(is_end).unwrap_or(rest.len());
+            tokens.push(Token::Word(&rest[..len]));
+            rest = &rest[len..];
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Cursor<'a> {
+    number: usize,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn error<T>(&self, kind: AssemblyErrorKind) -> AssemblyResult<T> {
+        Err(AssemblyError {
+            line: self.number,
+            kind,
+        })
+    }
+
+    fn next(&mut self) -> AssemblyResult<Token<'a>> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.clone())
+            },
+            None => self.error(AssemblyErrorKind::UnexpectedEndOfLine),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.position)
+    }
+
+    fn unexpected<T>(&self, token: Token) -> AssemblyResult<T> {
+        self.error(AssemblyErrorKind::UnexpectedToken(format!("{}", token)))
+    }
+
+    fn word(&mut self) -> AssemblyResult<&'a str> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            token => self.unexpected(token),
+        }
+    }
+
+    fn punct(&mut self, expected: char) -> AssemblyResult<()> {
+        match self.next()? {
+            Token::Punct(c) if c == expected => Ok(()),
+            token => self.unexpected(token),
+        }
+    }
+
+    /// Skips a comma if there's one
+    fn separator(&mut self) {
+        if self.peek() == Some(&Token::Punct(',')) {
+            self.position += 1;
+        }
+    }
+
+    fn operand(&mut self) -> AssemblyResult<Label<'a>> {
+        self.separator();
+        Ok(Label {
+            name: self.word()?,
+            line: self.number,
+        })
+    }
+
+    fn operands<const N: usize>(&mut self) -> AssemblyResult<[Label<'a>; N]> {
+        let mut operands = [Label::default(); N];
+        for operand in &mut operands {
+            *operand = self.operand()?;
+        }
+        Ok(operands)
+    }
+
+    fn color(&mut self) -> AssemblyResult<[Label<'a>; 2]> {
+        self.separator();
+        self.punct('[')?;
+        let color = self.operands()?;
+        self.punct(']')?;
+        Ok(color)
+    }
+
+    fn number(&mut self) -> AssemblyResult<Float> {
+        self.separator();
+        let word = self.word()?;
+        match word.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => self.error(AssemblyErrorKind::InvalidNumber(word.into())),
+        }
+    }
+
+    fn couple(&mut self) -> AssemblyResult<Couple> {
+        self.punct('(')?;
+        let x = self.number()?;
+        let y = self.number()?;
+        self.punct(')')?;
+        Ok(Couple::new(x, y))
+    }
+
+    /// Optional fill rule at the end of a line
+    fn fill_rule(&mut self) -> AssemblyResult<FillRule> {
+        let rule = match self.peek() {
+            Some(Token::Word("nonzero")) => FillRule::NonZero,
+            Some(Token::Word("evenodd")) => FillRule::EvenOdd,
+            _ => return Ok(FillRule::NonZero),
+        };
+        self.position += 1;
+        Ok(rule)
+    }
+
+    /// Optional interpolation at the end of a line
+    fn interpolation(&mut self) -> AssemblyResult<Interpolation> {
+        let interpolation = match self.peek() {
+            Some(Token::Word("step")) => Interpolation::Step,
+            Some(Token::Word("linear")) => Interpolation::Linear,
+            Some(Token::Word("smooth")) => Interpolation::Smooth,
+            _ => return Ok(Interpolation::Linear),
+        };
+        self.position += 1;
+        Ok(interpolation)
+    }
+
+    /// Optional `blend <mode>` at the end of a line
+    fn blend_mode(&mut self) -> AssemblyResult<BlendMode> {
+        if self.peek() != Some(&Token::Word("blend")) {
+            return Ok(BlendMode::SourceOver);
+        }
+        self.position += 1;
+        let word = self.word()?;
+        match (0..).map_while(BlendMode::from_index).find(|mode| mode.as_text() == word) {
+            Some(mode) => Ok(mode),
+            None => self.unexpected(Token::Word(word)),
+        }
+    }
+
+    fn end(&self) -> AssemblyResult<()> {
+        match self.peek() {
+            Some(token) => self.unexpected(token.clone()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+struct Label<'a> {
+    name: &'a str,
+    line: usize,
+}
+
+enum StackEntry<'a> {
+    Argument(Argument<String>),
+    Instruction(Operation, Vec<Label<'a>>),
+}
+
+enum Step<'a> {
+    Arc([Label<'a>; 3]),
+    CubicCurve([Label<'a>; 4]),
+    QuadraticCurve([Label<'a>; 3]),
+    Line([Label<'a>; 2]),
+    NewContour,
+}
+
+enum Layer<'a> {
+    Clip(Label<'a>, Label<'a>, FillRule, BlendMode),
+    Stroke(Label<'a>, Label<'a>, BlendMode),
+    Solid(Label<'a>, [Label<'a>; 2], FillRule, BlendMode),
+    /// opacity, number of steps in the group
+    Group(Label<'a>, usize, BlendMode),
+    PushClip(Label<'a>, FillRule),
+    PopClip,
+}
+
+type TriangleLabels<'a> = ([Label<'a>; 3], [[Label<'a>; 2]; 3]);
+
+#[derive(Default)]
+struct Parsed<'a> {
+    stack: Vec<(Label<'a>, StackEntry<'a>)>,
+    outputs: Vec<(Option<String>, Label<'a>)>,
+    tracks: Vec<Track<Label<'a>>>,
+    playback: Option<Playback>,
+    metadata: Vec<(String, String)>,
+    paths: BTreeMap<&'a str, Vec<Step<'a>>>,
+    backgrounds: BTreeMap<&'a str, Vec<TriangleLabels<'a>>>,
+    gradients: BTreeMap<&'a str, Gradient<Label<'a>>>,
+    strokers: BTreeMap<&'a str, Stroker<Label<'a>>>,
+    rendering_steps: Vec<Layer<'a>>,
+}
+
+fn operation(name: &str) -> Option<Operation> {
+    (0..).map_while(Operation::from_opcode).find(|op| op.as_text() == name)
+}
+
+fn define<'a, T>(map: &mut BTreeMap<&'a str, T>, label: Label<'a>, value: T) -> AssemblyResult<()> {
+    match map.insert(label.name, value) {
+        None => Ok(()),
+        Some(_) => Err(AssemblyError {
+            line: label.line,
+            kind: AssemblyErrorKind::DuplicateLabel(label.name.into()),
+        }),
+    }
+}
+
+fn lookup<T: Copy>(map: &BTreeMap<&str, T>, label: Label) -> AssemblyResult<T> {
+    map.get(label.name).copied().ok_or_else(|| AssemblyError {
+        line: label.line,
+        kind: AssemblyErrorKind::UnknownLabel(label.name.into()),
+    })
+}
+
+/// Parses railway assembly
+pub fn assemble(text: &str) -> AssemblyResult<Document> {
+    let mut parsed = Parsed::default();
+    let mut lines = text.lines().enumerate();
+    // rendering step index, line and clip count of unclosed groups
+    let mut groups = Vec::new();
+    // lines of the clips pushed and not popped yet
+    let mut clips: Vec<usize> = Vec::new();
+
+    while let Some((i, line)) = lines.next() {
+        let tokens = tokenize(line).map_err(|kind| AssemblyError { line: i + 1, kind })?;
+        let mut line = Cursor { number: i + 1, tokens, position: 0 };
+
+        if line.tokens.is_empty() {
+            continue;
+        }
+
+        let first = line.word()?;
+        if line.peek() == Some(&Token::Punct('=')) {
+            line.position += 1;
+            let label = Label { name: first, line: line.number };
+            let word = line.word()?;
+            let entry = if word == "arg" {
+                let name = match line.peek() {
+                    Some(Token::Str(name)) => Some(name.clone()),
+                    _ => None,
+                };
+                if name.is_some() {
+                    line.position += 1;
+                }
+                let value = line.couple()?;
+                let mut range = (value, value);
+                if line.peek() == Some(&Token::Word("range")) {
+                    line.position += 1;
+                    range = (line.couple()?, line.couple()?);
+                }
+                StackEntry::Argument(Argument { name, value, range })
+            } else {
+                let Some(operation) = operation(word) else {
+                    return line.error(AssemblyErrorKind::UnknownOperation(word.into()));
+                };
+                let mut operands = Vec::new();
+                while line.peek().is_some() && operands.len() < 3 {
+                    operands.push(line.operand()?);
+                }
+                if operands.len() < operation.number_of_operands() as usize {
+                    return line.error(AssemblyErrorKind::MissingOperands);
+                }
+                StackEntry::Instruction(operation, operands)
+            };
+            parsed.stack.push((label, entry));
+            line.end()?;
+            continue;
+        }
+
+        match first {
+            "output" => {
+                let name = match line.peek() {
+                    Some(Token::Str(name)) => Some(name.clone()),
+                    _ => None,
+                };
+                if name.is_some() {
+                    line.position += 1;
+                }
+                let [address] = line.operands()?;
+                parsed.outputs.push((name, address));
+            },
+            "path" | "background" | "gradient" => {
+                let label = line.operand()?;
+                let mut gradient = Gradient {
+                    kind: GradientKind::Linear,
+                    points: [label; 3],
+                    stops: Vec::new(),
+                };
+                if first == "gradient" {
+                    gradient.kind = match line.word()? {
+                        "linear" => GradientKind::Linear,
+                        "radial" => GradientKind::Radial,
+                        "conic" => GradientKind::Conic,
+                        word => return line.unexpected(Token::Word(word)),
+                    };
+                    let [a, b] = line.operands()?;
+                    let c = match line.peek() {
+                        Some(_) => line.operand()?,
+                        None => a,
+                    };
+                    gradient.points = [a, b, c];
+                }
+                line.end()?;
+                let mut steps = Vec::new();
+                let mut triangles = Vec::new();
+
+                loop {
+                    let Some((i, text)) = lines.next() else {
+                        return line.error(AssemblyErrorKind::UnclosedBlock);
+                    };
+                    let tokens = tokenize(text).map_err(|kind| AssemblyError { line: i + 1, kind })?;
+                    line = Cursor { number: i + 1, tokens, position: 0 };
+
+                    if line.tokens.is_empty() {
+                        continue;
+                    }
+
+                    match (first, line.word()?) {
+                        (_, "end") => break,
+                        ("path", "arc") => steps.push(Step::Arc(line.operands()?)),
+                        ("path", "cubic") => steps.push(Step::CubicCurve(line.operands()?)),
+                        ("path", "quadratic") => steps.push(Step::QuadraticCurve(line.operands()?)),
+                        ("path", "line") => steps.push(Step::Line(line.operands()?)),
+                        ("path", "contour") => steps.push(Step::NewContour),
+                        ("background", "triangle") => {
+                            let points = line.operands()?;
+                            let colors = [line.color()?, line.color()?, line.color()?];
+                            triangles.push((points, colors));
+                        },
+                        ("gradient", "stop") => {
+                            let [offset] = line.operands()?;
+                            gradient.stops.push(ColorStop { offset, color: line.color()? });
+                        },
+                        (_, word) => return line.unexpected(Token::Word(word)),
+                    }
+                    line.end()?;
+                }
+
+                // clips refer to backgrounds and gradients alike
+                let shared = parsed.backgrounds.contains_key(label.name) || parsed.gradients.contains_key(label.name);
+                if first != "path" && shared {
+                    return Err(AssemblyError {
+                        line: label.line,
+                        kind: AssemblyErrorKind::DuplicateLabel(label.name.into()),
+                    });
+                }
+
+                match first {
+                    "path" => define(&mut parsed.paths, label, steps)?,
+                    "background" => define(&mut parsed.backgrounds, label, triangles)?,
+                    _ => define(&mut parsed.gradients, label, gradient)?,
+                }
+            },
+            "track" => {
+                let argument = line.operand()?;
+                line.end()?;
+                let mut keyframes = Vec::new();
+
+                loop {
+                    let Some((i, text)) = lines.next() else {
+                        return line.error(AssemblyErrorKind::UnclosedBlock);
+                    };
+                    let tokens = tokenize(text).map_err(|kind| AssemblyError { line: i + 1, kind })?;
+                    line = Cursor { number: i + 1, tokens, position: 0 };
+
+                    if line.tokens.is_empty() {
+                        continue;
+                    }
+
+                    match line.word()? {
+                        "end" => break,
+                        "key" => {
+                            let time = line.number()?;
+                            line.separator();
+                            let value = line.couple()?;
+                            let interpolation = line.interpolation()?;
+                            keyframes.push(Keyframe { time, value, interpolation });
+                        },
+                        word => return line.unexpected(Token::Word(word)),
+                    }
+                    line.end()?;
+                }
+
+                parsed.tracks.push(Track { argument, keyframes });
+            },
+            "playback" if parsed.playback.is_none() => {
+                let mut playback = Playback::new(line.number()?);
+                while line.peek().is_some() {
+                    match line.word()? {
+                        "once" => playback.loop_mode = LoopMode::Once,
+                        "loop" => playback.loop_mode = LoopMode::Loop,
+                        "pingpong" => playback.loop_mode = LoopMode::PingPong,
+                        "fps" => playback.fps = line.number()?,
+                        word => return line.unexpected(Token::Word(word)),
+                    }
+                }
+                parsed.playback = Some(playback);
+            },
+            "meta" => {
+                let [key, value] = [line.next()?, line.next()?].map(|token| match token {
+                    Token::Str(string) => Ok(string),
+                    token => line.unexpected(token),
+                });
+                parsed.metadata.push((key?, value?));
+            },
+            "stroker" => {
+                let label = line.operand()?;
+                let [pattern, width] = line.operands()?;
+                let mut stroker = Stroker::new(pattern, width, line.color()?);
+
+                while line.peek().is_some() {
+                    match line.word()? {
+                        "open" => stroker.closed = false,
+                        "cap" => stroker.cap = match line.word()? {
+                            "butt" => LineCap::Butt,
+                            "round" => LineCap::Round,
+                            "square" => LineCap::Square,
+                            word => return line.unexpected(Token::Word(word)),
+                        },
+                        "join" => stroker.join = match line.word()? {
+                            "miter" => LineJoin::Miter,
+                            "round" => LineJoin::Round,
+                            "bevel" => LineJoin::Bevel,
+                            word => return line.unexpected(Token::Word(word)),
+                        },
+                        "miter" => match line.number()? {
+                            limit if limit >= 1.0 => stroker.miter_limit = limit,
+                            limit => return line.error(AssemblyErrorKind::InvalidNumber(format!("{}", limit))),
+                        },
+                        word => return line.unexpected(Token::Word(word)),
+                    }
+                }
+
+                define(&mut parsed.strokers, label, stroker)?;
+            },
+            "clip" => {
+                let [path] = line.operands()?;
+                line.separator();
+                if line.peek() == Some(&Token::Punct('[')) {
+                    let color = line.color()?;
+                    parsed.rendering_steps.push(Layer::Solid(path, color, line.fill_rule()?, line.blend_mode()?));
+                } else {
+                    let [background] = line.operands()?;
+                    parsed.rendering_steps.push(Layer::Clip(path, background, line.fill_rule()?, line.blend_mode()?));
+                }
+            },
+            "stroke" => {
+                let [path, stroker] = line.operands()?;
+                parsed.rendering_steps.push(Layer::Stroke(path, stroker, line.blend_mode()?));
+            },
+            "group" => {
+                let [opacity] = line.operands()?;
+                groups.push((parsed.rendering_steps.len(), line.number, clips.len()));
+                parsed.rendering_steps.push(Layer::Group(opacity, 0, line.blend_mode()?));
+            },
+            "push" => {
+                let [path] = line.operands()?;
+                parsed.rendering_steps.push(Layer::PushClip(path, line.fill_rule()?));
+                clips.push(line.number);
+            },
+            "pop" => {
+                let outer = groups.last().map_or(0, |(_, _, clips)| *clips);
+                if clips.len() <= outer {
+                    return Err(AssemblyError {
+                        line: line.number,
+                        kind: AssemblyErrorKind::UnbalancedClip,
+                    });
+                }
+                clips.pop();
+                parsed.rendering_steps.push(Layer::PopClip);
+            },
+            "end" if !groups.is_empty() => {
+                let (group, _, outer) = groups.pop().unwrap();
+                if let Some(push) = clips.get(outer) {
+                    return Err(AssemblyError {
+                        line: *push,
+                        kind: AssemblyErrorKind::UnbalancedClip,
+                    });
+                }
+                let steps = parsed.rendering_steps.len() - group - 1;
+                if let Layer::Group(_, length, _) = &mut parsed.rendering_steps[group] {
+                    *length = steps;
+                }
+            },
+            word => return line.unexpected(Token::Word(word)),
+        }
+
+        line.end()?;
+    }
+
+    if let Some((_, line, _)) = groups.pop() {
+        return Err(AssemblyError {
+            line,
+            kind: AssemblyErrorKind::UnclosedBlock,
+        });
+    }
+
+    if let Some(push) = clips.first() {
+        return Err(AssemblyError {
+            line: *push,
+            kind: AssemblyErrorKind::UnbalancedClip,
+        });
+    }
+
+    parsed.resolve()
+}
+
+impl Parsed<'_> {
+    fn resolve(self) -> AssemblyResult<Document> {
+        let mut document = Document::new();
+        let mut addresses = BTreeMap::new();
+
+        let arguments = self.stack.iter().filter(|(_, e)| matches!(e, StackEntry::Argument(_)));
+        let instructions = self.stack.iter().filter(|(_, e)| matches!(e, StackEntry::Instruction(..)));
+        for (address, (label, _)) in arguments.clone().chain(instructions.clone()).enumerate() {
+            define(&mut addresses, *label, address)?;
+        }
+
+        for (_, entry) in arguments {
+            if let StackEntry::Argument(argument) = entry {
+                document.arguments.push(argument.clone());
+            }
+        }
+
+        for (i, (_, entry)) in instructions.enumerate() {
+            if let StackEntry::Instruction(operation, labels) = entry {
+                let current = document.arguments.len() + i;
+                let mut operands = [0; 3];
+                for (operand, label) in operands.iter_mut().zip(labels) {
+                    *operand = lookup(&addresses, *label)?;
+                    if *operand >= current {
+                        return Err(AssemblyError {
+                            line: label.line,
+                            kind: AssemblyErrorKind::NotComputedYet(label.name.into()),
+                        });
+                    }
+                }
+                // the matrix of Affine2 spans three couples
+                if *operation == Operation::Affine2 && operands[1] + 2 >= current {
+                    return Err(AssemblyError {
+                        line: labels[1].line,
+                        kind: AssemblyErrorKind::NotComputedYet(labels[1].name.into()),
+                    });
+                }
+                document.instructions.push(Instruction { operation: *operation, operands });
+            }
+        }
+
+        for (name, label) in &self.outputs {
+            document.outputs.push(Output {
+                name: name.clone(),
+                address: lookup(&addresses, *label)?,
+            });
+        }
+
+        for track in self.tracks {
+            let argument = lookup(&addresses, track.argument)?;
+            if argument >= document.arguments.len() {
+                return Err(AssemblyError {
+                    line: track.argument.line,
+                    kind: AssemblyErrorKind::NotAnArgument(track.argument.name.into()),
+                });
+            }
+            document.tracks.push(track.map(|_| argument));
+        }
+
+        document.playback = self.playback;
+        document.metadata = self.metadata;
+
+        let address = |label: Label| lookup(&addresses, label);
+        let all = |labels: &[Label]| labels.iter().map(|l| lookup(&addresses, *l)).collect::<AssemblyResult<Vec<_>>>();
+
+        for layer in &self.rendering_steps {
+            let path = match layer {
+                Layer::Clip(path, ..) | Layer::Stroke(path, ..) | Layer::Solid(path, ..) | Layer::PushClip(path, _) => path,
+                Layer::Group(opacity, length, mode) => {
+                    document.rendering_steps.push(RenderingStep::Group(*length, address(*opacity)?, *mode));
+                    continue;
+                },
+                Layer::PopClip => {
+                    document.rendering_steps.push(RenderingStep::PopClip);
+                    continue;
+                },
+            };
+            let Some(steps) = self.paths.get(path.name) else {
+                return Err(AssemblyError {
+                    line: path.line,
+                    kind: AssemblyErrorKind::UnknownLabel(path.name.into()),
+                });
+            };
+
+            let mut path = Vec::with_capacity(steps.len());
+            for step in steps {
+                path.push(match step {
+                    Step::Arc(labels) => {
+                        let a = all(labels)?;
+                        PathStep::Arc(Arc {
+                            start_point: a[0],
+                            center: a[1],
+                            deltas: a[2],
+                        })
+                    },
+                    Step::CubicCurve(labels) => {
+                        let a = all(labels)?;
+                        PathStep::CubicCurve(CubicCurve { points: [a[0], a[1], a[2], a[3]] })
+                    },
+                    Step::QuadraticCurve(labels) => {
+                        let a = all(labels)?;
+                        PathStep::QuadraticCurve(QuadraticCurve { points: [a[0], a[1], a[2]] })
+                    },
+                    Step::Line(labels) => {
+                        let a = all(labels)?;
+                        PathStep::Line(Line { points: [a[0], a[1]] })
+                    },
+                    Step::NewContour => PathStep::NewContour,
+                });
+            }
+
+            document.rendering_steps.push(match layer {
+                Layer::Clip(_, gradient, rule, mode) if self.gradients.contains_key(gradient.name) => {
+                    let gradient = &self.gradients[gradient.name];
+                    let [p0, p1, p2] = gradient.points;
+                    let mut stops = Vec::with_capacity(gradient.stops.len());
+                    for stop in &gradient.stops {
+                        let [rg, ba] = stop.color;
+                        stops.push(ColorStop {
+                            offset: address(stop.offset)?,
+                            color: [address(rg)?, address(ba)?],
+                        });
+                    }
+                    RenderingStep::Gradient(path, Gradient {
+                        kind: gradient.kind,
+                        points: [address(p0)?, address(p1)?, address(p2)?],
+                        stops,
+                    }, *rule, *mode)
+                },
+                Layer::Clip(_, background, rule, mode) => {
+                    let Some(triangles) = self.backgrounds.get(background.name) else {
+                        return Err(AssemblyError {
+                            line: background.line,
+                            kind: AssemblyErrorKind::UnknownLabel(background.name.into()),
+                        });
+                    };
+                    let mut background = Vec::with_capacity(triangles.len());
+                    for (points, colors) in triangles {
+                        let p = all(points)?;
+                        let c = all(colors.as_flattened())?;
+                        background.push(Triangle {
+                            points: [p[0], p[1], p[2]],
+                            colors: [[c[0], c[1]], [c[2], c[3]], [c[4], c[5]]],
+                        });
+                    }
+                    RenderingStep::Clip(path, background, *rule, *mode)
+                },
+                Layer::Solid(_, [rg, ba], rule, mode) => {
+                    RenderingStep::Solid(path, [address(*rg)?, address(*ba)?], *rule, *mode)
+                },
+                Layer::Stroke(_, stroker, mode) => {
+                    let stroker = lookup(&self.strokers, *stroker)?;
+                    let [rg, ba] = stroker.color;
+                    RenderingStep::Stroke(path, Stroker {
+                        pattern: address(stroker.pattern)?,
+                        width: address(stroker.width)?,
+                        color: [address(rg)?, address(ba)?],
+                        ..stroker.map(|_| 0)
+                    }, *mode)
+                },
+                Layer::PushClip(_, rule) => RenderingStep::PushClip(path, *rule),
+                Layer::Group(..) | Layer::PopClip => unreachable!(),
+            });
+        }
+
+        Ok(document)
+    }
+}
+
+fn write_string<W: fmt::Write>(w: &mut W, string: &str) -> fmt::Result {
+    w.write_char('"')?;
+    for c in string.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            '\n' => w.write_str("\\n")?,
+            '\r' => w.write_str("\\r")?,
+            '\t' => w.write_str("\\t")?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    let valid_start = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_');
+    valid_start && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn unique(mut label: String, used: &mut Vec<String>) -> String {
+    while used.contains(&label) {
+        label.push('_');
+    }
+    used.push(label.clone());
+    label
+}
+
+struct Couples<'a>(&'a [Couple]);
+
+impl fmt::Display for Couples<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, couple) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "({:?}, {:?})", couple.x, couple.y)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a railway program to railway assembly
+///
+/// Stack entries are labelled with the name of their argument or output
+/// when it's a valid label, other labels are generated.
+pub fn disassemble<T: AsRef<[u8]>>(program: &SerializedProgram<T>) -> ParsingResult<String> {
+    program.validate()?;
+
+    let arguments = program.arguments();
+    let mut labels = vec![None; program.stack_size()];
+    let mut used = Vec::new();
+
+    let named_outputs = (0..program.outputs()).map(|i| program.output(i).map(|o| (o.name, o.address)));
+    let named_arguments = (0..arguments).map(|i| program.argument(i).map(|a| (a.name, i)));
+    for named in named_arguments.chain(named_outputs) {
+        if let (Some(name), address) = named? {
+            if labels[address].is_none() && is_label(name) && !used.iter().any(|u| u == name) {
+                labels[address] = Some(unique(name.into(), &mut used));
+            }
+        }
+    }
+
+    for (address, label) in labels.iter_mut().enumerate() {
+        if label.is_none() {
+            *label = Some(unique(format!("s{}", address), &mut used));
+        }
+    }
+
+    let labels: Vec<String> = labels.into_iter().flatten().collect();
+    let mut text = String::new();
+
+    for (i, label) in labels[..arguments].iter().enumerate() {
+        let argument = program.argument(i)?;
+        text += &format!("{} = arg ", label);
+        if let Some(name) = argument.name {
+            let _ = write_string(&mut text, name);
+            text += " ";
+        }
+        text += &format!("{}", Couples(&[argument.value]));
+        if argument.range != (argument.value, argument.value) {
+            let (min, max) = argument.range;
+            text += &format!(" range {}", Couples(&[min, max]));
+        }
+        text += "\n";
+    }
+
+    // blank line between groups of statements
+    let separate = |text: &mut String, group_len| if group_len > 0 && !text.is_empty() {
+        text.push('\n');
+    };
+
+    separate(&mut text, program.instructions());
+    for i in 0..program.instructions() {
+        let instruction = program.instruction(i)?;
+        let operation = instruction.operation;
+        text += &format!("{} = {}", labels[arguments + i], operation.as_text());
+
+        // unused operands are only written when they aren't zero
+        let used_operands = operation.number_of_operands() as usize;
+        let written = instruction.operands.iter().rposition(|a| *a != 0).map_or(0, |p| p + 1);
+        for (j, operand) in instruction.operands[..written.max(used_operands)].iter().enumerate() {
+            text += if j == 0 { " " } else { ", " };
+            text += &labels[*operand];
+        }
+        text += "\n";
+    }
+
+    separate(&mut text, program.outputs());
+    for i in 0..program.outputs() {
+        let output = program.output(i)?;
+        text += "output ";
+        if let Some(name) = output.name {
+            let _ = write_string(&mut text, name);
+            text += " ";
+        }
+        text += &labels[output.address];
+        text += "\n";
+    }
+
+    for i in 0..program.tracks() {
+        separate(&mut text, 1);
+        let track = program.track(i)?;
+        text += &format!("track {}\n", labels[track.argument]);
+        for keyframe in track.keyframes {
+            text += &format!("    key {:?} {}", keyframe.time, Couples(&[keyframe.value]));
+            text += match keyframe.interpolation {
+                Interpolation::Step => " step\n",
+                Interpolation::Linear => "\n",
+                Interpolation::Smooth => " smooth\n",
+            };
+        }
+        text += "end\n";
+    }
+
+    if let Some(playback) = program.playback()? {
+        separate(&mut text, 1);
+        let default = Playback::new(playback.duration);
+        text += &format!("playback {:?}", playback.duration);
+        text += match playback.loop_mode {
+            LoopMode::Once => "",
+            LoopMode::Loop => " loop",
+            LoopMode::PingPong => " pingpong",
+        };
+        if playback.fps != default.fps {
+            text += &format!(" fps {:?}", playback.fps);
+        }
+        text += "\n";
+    }
+
+    separate(&mut text, program.metadata_entries());
+    for i in 0..program.metadata_entries() {
+        let (key, value) = program.metadata_entry(i)?;
+        text += "meta ";
+        let _ = write_string(&mut text, key);
+        text += " ";
+        let _ = write_string(&mut text, value);
+        text += "\n";
+    }
+
+    let list = |addresses: &[Address]| {
+        let labels: Vec<_> = addresses.iter().map(|a| labels[*a].as_str()).collect();
+        labels.join(", ")
+    };
+
+    for i in 0..program.paths() {
+        separate(&mut text, 1);
+        text += &format!("path path{}\n", i);
+        for step in program.path(i)? {
+            text += &match step? {
+                PathStep::Arc(arc) => format!("    arc {}\n", list(&[arc.start_point, arc.center, arc.deltas])),
+                PathStep::CubicCurve(curve) => format!("    cubic {}\n", list(&curve.points)),
+                PathStep::QuadraticCurve(curve) => format!("    quadratic {}\n", list(&curve.points)),
+                PathStep::Line(line) => format!("    line {}\n", list(&line.points)),
+                PathStep::NewContour => "    contour\n".into(),
+            };
+        }
+        text += "end\n";
+    }
+
+    for i in 0..program.backgrounds() {
+        separate(&mut text, 1);
+        text += &format!("background background{}\n", i);
+        for triangle in program.background(i)? {
+            let triangle = triangle?;
+            let [c0, c1, c2] = triangle.colors;
+            let colors = [list(&c0), list(&c1), list(&c2)];
+            text += &format!("    triangle {}, [{}], [{}], [{}]\n", list(&triangle.points), colors[0], colors[1], colors[2]);
+        }
+        text += "end\n";
+    }
+
+    for i in 0..program.gradients() {
+        separate(&mut text, 1);
+        let gradient = program.gradient(i)?;
+        let [p0, p1, p2] = gradient.points;
+        let kind = match gradient.kind {
+            GradientKind::Linear => "linear",
+            GradientKind::Radial => "radial",
+            GradientKind::Conic => "conic",
+        };
+        text += &format!("gradient gradient{} {} {}", i, kind, list(&[p0, p1]));
+        // the third point defaults to the first one
+        if p2 != p0 {
+            text += &format!(", {}", labels[p2]);
+        }
+        text += "\n";
+        for stop in gradient.stops {
+            text += &format!("    stop {}, [{}]\n", labels[stop.offset], list(&stop.color));
+        }
+        text += "end\n";
+    }
+
+    separate(&mut text, program.strokers());
+    for i in 0..program.strokers() {
+        let stroker = program.stroker(i)?;
+        text += &format!("stroker stroker{} {}, [{}]", i, list(&[stroker.pattern, stroker.width]), list(&stroker.color));
+        if !stroker.closed {
+            text += " open";
+        }
+        match stroker.cap {
+            LineCap::Butt => text += " cap butt",
+            LineCap::Round => (),
+            LineCap::Square => text += " cap square",
+        }
+        match stroker.join {
+            LineJoin::Miter => text += " join miter",
+            LineJoin::Round => (),
+            LineJoin::Bevel => text += " join bevel",
+        }
+        if stroker.miter_limit != Stroker::new(0, 0, [0; 2]).miter_limit {
+            text += &format!(" miter {:?}", stroker.miter_limit);
+        }
+        text += "\n";
+    }
+
+    // last step of the groups around the current one
+    let mut groups: Vec<usize> = Vec::new();
+    let close = |text: &mut String, groups: &mut Vec<usize>, step| {
+        while groups.last().is_some_and(|end| *end < step) {
+            groups.pop();
+            *text += &"    ".repeat(groups.len());
+            *text += "end\n";
+        }
+    };
+
+    separate(&mut text, program.rendering_steps());
+    for i in 0..program.rendering_steps() {
+        close(&mut text, &mut groups, i);
+        text += &"    ".repeat(groups.len());
+
+        let (step, rule, mode) = match program.raw_rendering_step(i)? {
+            RawRenderingStep::Clip(p, b, rule, mode) => (format!("clip path{}, background{}", p, b), rule, mode),
+            RawRenderingStep::Stroke(p, s, mode) => (format!("stroke path{}, stroker{}", p, s), FillRule::NonZero, mode),
+            RawRenderingStep::Gradient(p, g, rule, mode) => (format!("clip path{}, gradient{}", p, g), rule, mode),
+            RawRenderingStep::Solid(p, c, rule, mode) => (format!("clip path{}, [{}]", p, list(&program.color(c)?)), rule, mode),
+            RawRenderingStep::Group(length, opacity, mode) => {
+                groups.push(i + length);
+                (format!("group {}", labels[opacity]), FillRule::NonZero, mode)
+            },
+            RawRenderingStep::PushClip(p, rule) => (format!("push path{}", p), rule, BlendMode::SourceOver),
+            RawRenderingStep::PopClip => ("pop".into(), FillRule::NonZero, BlendMode::SourceOver),
+        };
+        text += &step;
+        if rule == FillRule::EvenOdd {
+            text += " evenodd";
+        }
+        if mode != BlendMode::SourceOver {
+            text += &format!(" blend {}", mode.as_text());
+        }
+        text += "\n";
+    }
+    close(&mut text, &mut groups, program.rendering_steps());
+
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Uses every statement and step kind
+    const PROGRAM: &str = r#"
+        size = arg "size" (200.0, 200.0) range (0.0, 0.0) (1000.0, 1000.0)
+        time = arg "time" (0.0, 0.0)
+        f = arg (0.05, 0.05)
+        one = arg (1.0, 1.0)
+        zero = arg (0.0, 0.0)
+        rg = arg (1.0, 0.5)
+        ba = arg (0.25, 1.0)
+        a = Multiply2 size, f
+        b = Subtract2 size, a
+        c = Select2 a, b
+        d = Select2 b, a
+        center = Multiply2 size, one
+        mapped = Affine2 center, f
+        output "center" center
+
+        track f
+            key 0.0 (0.05, 0.05) step
+            key 1.0 (0.1, 0.1)
+            key 2.0 (0.05, 0.05) smooth
+        end
+
+        playback 2.0 loop fps 30.0
+        meta "title" "Every step"
+        meta "width" "200"
+        meta "height" "200"
+
+        path frame
+            line a, c
+            quadratic c, b, d
+            cubic d, a, b, c
+            arc a, center, one
+            contour
+            line a, b
+        end
+
+        background mesh
+            triangle a, b, c, [rg, ba], [rg, ba], [rg, ba]
+        end
+
+        gradient lin linear a, b
+            stop zero, [rg, ba]
+            stop one, [ba, rg]
+        end
+
+        gradient rad radial center, one, a
+            stop zero, [rg, ba]
+        end
+
+        gradient con conic center, one
+            stop one, [rg, ba]
+        end
+
+        stroker solid one, f, [rg, ba]
+        stroker dashed f, one, [rg, ba] open cap square join miter miter 10.0
+
+        clip frame, mesh
+        clip frame, lin evenodd
+        clip frame, rad blend multiply
+        clip frame, con
+        clip frame, [rg, ba] blend screen
+        stroke frame, solid
+        stroke frame, dashed blend destination-out
+
+        group one blend screen
+            push frame evenodd
+            clip frame, mesh
+            pop
+        end
+    "#;
+
+    fn error(text: &str) -> AssemblyError {
+        assemble(text).unwrap_err()
+    }
+
+    #[test]
+    fn text_to_bytes_to_text() {
+        let bytes = assemble(PROGRAM).unwrap().serialize();
+        let text = disassemble(&SerializedProgram::new(&bytes[..]).unwrap()).unwrap();
+
+        let again = assemble(&text).unwrap().serialize();
+        assert_eq!(bytes, again);
+        assert_eq!(text, disassemble(&SerializedProgram::new(&again[..]).unwrap()).unwrap());
+    }
+
+    #[test]
+    fn bytes_to_text_to_bytes() {
+        let document = assemble(PROGRAM).unwrap();
+        let bytes = document.serialize();
+        let program = SerializedProgram::new(&bytes[..]).unwrap();
+
+        let text = disassemble(&program).unwrap();
+        assert_eq!(assemble(&text).unwrap().serialize(), bytes);
+        assert_eq!(Document::from_program(&program).unwrap(), document);
+    }
+
+    #[test]
+    fn errors_have_lines() {
+        let cases = [
+            ("a = arg (1.0, 1.0)\nb = Frobnicate2 a, a", 2, AssemblyErrorKind::UnknownOperation("Frobnicate2".into())),
+            ("a = arg (1.0, 1.0)\nb = Add2 a, c\nc = Add2 a, a", 2, AssemblyErrorKind::NotComputedYet("c".into())),
+            ("a = arg (1.0, 1.0)\nb = Add2 a, nothing", 2, AssemblyErrorKind::UnknownLabel("nothing".into())),
+            ("a = arg (1.0, 1.0)\nm = arg (1.0, 1.0)\nb = Affine2 a, m", 3, AssemblyErrorKind::NotComputedYet("m".into())),
+            ("a = arg (1.0, 1.0)\n\nstroker s a, a, [a, a]\nstroke frame, s", 4, AssemblyErrorKind::UnknownLabel("frame".into())),
+        ];
+
+        for (text, line, kind) in cases {
+            assert_eq!(error(text), AssemblyError { line, kind });
+        }
+    }
+}
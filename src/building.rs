@@ -0,0 +1,1012 @@
+//! Symbolic construction of railway programs
+//!
+//! ```
+//! use railway::building::ProgramBuilder;
+//! use railway::computing::{Couple, PathStep, Line, Stroker};
+//!
+//! let mut b = ProgramBuilder::new();
+//! let size = b.arg("size", Couple::new(200.0, 200.0));
+//! let f = b.constant(Couple::new(0.05, 0.05));
+//! let top_left = b.mul(size, f);
+//! let bottom_right = b.sub(size, top_left);
+//! let color = b.constant(Couple::new(1.0, 1.0));
+//!
+//! let diagonal = [PathStep::Line(Line { points: [top_left, bottom_right] })];
+//! let mut stroker = Stroker::new(f, f, [color, color]);
+//! stroker.closed = false;
+//! b.stroke(&diagonal, stroker);
+//!
+//! let bytes = b.serialize();
+//! ```
+//!
+//! Values can also be combined as [`Expr`]essions, which are lowered to
+//! instructions by [`ProgramBuilder::eval`]:
+//!
+//! ```
+//! # use railway::building::ProgramBuilder;
+//! # use railway::computing::Couple;
+//! let mut b = ProgramBuilder::new();
+//! let center = b.arg("center", Couple::new(100.0, 100.0));
+//! let radius = b.arg("radius", Couple::new(40.0, 0.0));
+//!
+//! let point = b.eval(center + radius.cartesian());
+//! let again = b.eval(center + radius.cartesian());
+//! assert_eq!(point, again);
+//! ```
+
+use crate::computing::*;
+use crate::editing::Document;
+
+use alloc::{vec::Vec, string::String, rc::Rc, collections::BTreeMap};
+use core::ops;
+
+/// Handle to a couple of the stack
+///
+/// Its address is only known once every argument has been declared,
+/// in [`ProgramBuilder::build`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Value(Slot);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Slot {
+    Argument(usize),
+    Computed(usize),
+}
+
+type SymbolicStep = RenderingStep<Vec<PathStep<Value>>, Vec<Triangle<Value>>, Value>;
+
+/// Builds a railway program, keeping track of stack addresses
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ProgramBuilder {
+    arguments: Vec<Argument<String>>,
+    instructions: Vec<(Operation, [Value; 3])>,
+    outputs: Vec<(String, Value)>,
+    tracks: Vec<Track<Value>>,
+    playback: Option<Playback>,
+    metadata: Vec<(String, String)>,
+    rendering_steps: Vec<SymbolicStep>,
+    blend_mode: BlendMode,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares an argument
+    pub fn argument(&mut self, argument: Argument<String>) -> Value {
+        self.arguments.push(argument);
+        Value(Slot::Argument(self.arguments.len() - 1))
+    }
+
+    /// Declares a named argument
+    pub fn arg(&mut self, name: &str, value: Couple) -> Value {
+        self.argument(Argument::named(name.into(), value))
+    }
+
+    /// Declares the [`TIME_ARGUMENT`], unless it exists
+    pub fn time(&mut self) -> Value {
+        let existing = self.arguments.iter().position(|a| a.name.as_deref() == Some(TIME_ARGUMENT));
+        match existing {
+            Some(i) => Value(Slot::Argument(i)),
+            None => self.arg(TIME_ARGUMENT, C_ZERO),
+        }
+    }
+
+    /// Declares an unnamed argument, unless an identical one exists
+    pub fn constant(&mut self, value: Couple) -> Value {
+        let existing = self.arguments.iter().position(|a| *a == Argument::unnamed(value));
+        match existing {
+            Some(i) => Value(Slot::Argument(i)),
+            None => self.argument(Argument::unnamed(value)),
+        }
+    }
+
+    /// Appends an instruction, unless an identical one exists;
+    /// operands which the operation doesn't use are ignored and
+    /// serialized as zero.
+    pub fn instruction(&mut self, operation: Operation, operands: [Value; 3]) -> Value {
+        let used = operation.number_of_operands() as usize;
+        let mut operands = operands;
+        let first = operands[0];
+        operands[used..].fill(first);
+
+        let instruction = (operation, operands);
+        let i = match self.instructions.iter().position(|i| *i == instruction) {
+            Some(i) => i,
+            None => {
+                self.instructions.push(instruction);
+                self.instructions.len() - 1
+            },
+        };
+
+        Value(Slot::Computed(i))
+    }
+
+    /// Appends the instructions of an expression
+    ///
+    /// Subexpressions which were already computed are reused.
+    pub fn eval<E: Into<Expr>>(&mut self, expr: E) -> Value {
+        self.eval_node(&expr.into(), &mut BTreeMap::new())
+    }
+
+    /// Evaluates each node shared by several operands only once
+    fn eval_node(&mut self, expr: &Expr, evaluated: &mut BTreeMap<*const Node, Value>) -> Value {
+        let node = Rc::as_ptr(&expr.0);
+        if let Some(value) = evaluated.get(&node) {
+            return *value;
+        }
+
+        let value = match &*expr.0 {
+            Node::Value(value) => *value,
+            Node::Constant(couple) => self.constant(*couple),
+            Node::Operation(operation, operands) => {
+                let used = operation.number_of_operands() as usize;
+                let mut values = [Value(Slot::Argument(0)); 3];
+                for (value, operand) in values.iter_mut().zip(&operands[..used]) {
+                    *value = self.eval_node(operand, evaluated);
+                }
+                self.instruction(*operation, values)
+            },
+        };
+
+        evaluated.insert(node, value);
+        value
+    }
+
+    fn unary(&mut self, operation: Operation, a: Value) -> Value {
+        self.instruction(operation, [a; 3])
+    }
+
+    fn binary(&mut self, operation: Operation, a: Value, b: Value) -> Value {
+        self.instruction(operation, [a, b, a])
+    }
+
+    pub fn add(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Add2, a, b)
+    }
+
+    pub fn sub(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Subtract2, a, b)
+    }
+
+    pub fn mul(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Multiply2, a, b)
+    }
+
+    pub fn div(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Divide2, a, b)
+    }
+
+    /// X of `a` and Y of `b`
+    pub fn select(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Select2, a, b)
+    }
+
+    /// X of `a` and X of `b`
+    pub fn each_x(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::EachX2, a, b)
+    }
+
+    /// Y of `a` and Y of `b`
+    pub fn each_y(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::EachY2, a, b)
+    }
+
+    pub fn polar(&mut self, a: Value) -> Value {
+        self.unary(Operation::Polar1, a)
+    }
+
+    pub fn cartesian(&mut self, a: Value) -> Value {
+        self.unary(Operation::Cartesian1, a)
+    }
+
+    /// Cartesian coordinates of `polar`, added to `origin`
+    pub fn cartesian_from(&mut self, polar: Value, origin: Value) -> Value {
+        self.binary(Operation::Cartesian2, polar, origin)
+    }
+
+    pub fn inside(&mut self, a: Value, min: Value, max: Value) -> Value {
+        self.instruction(Operation::Inside3, [a, min, max])
+    }
+
+    pub fn swap(&mut self, a: Value) -> Value {
+        self.unary(Operation::Swap1, a)
+    }
+
+    /// `a * weights.x + b * weights.y`
+    pub fn adjusted(&mut self, a: Value, b: Value, weights: Value) -> Value {
+        self.instruction(Operation::Adjusted3, [a, b, weights])
+    }
+
+    pub fn clamp(&mut self, a: Value, min: Value, max: Value) -> Value {
+        self.instruction(Operation::Clamp3, [a, min, max])
+    }
+
+    /// `a` rotated around `pivot` by `angle.x` radians
+    pub fn rotate(&mut self, a: Value, pivot: Value, angle: Value) -> Value {
+        self.instruction(Operation::Rotate3, [a, pivot, angle])
+    }
+
+    /// `a` scaled from `pivot` by `factors`
+    pub fn scale(&mut self, a: Value, pivot: Value, factors: Value) -> Value {
+        self.instruction(Operation::Scale3, [a, pivot, factors])
+    }
+
+    pub fn sin(&mut self, a: Value) -> Value {
+        self.unary(Operation::Sin1, a)
+    }
+
+    pub fn cos(&mut self, a: Value) -> Value {
+        self.unary(Operation::Cos1, a)
+    }
+
+    pub fn tan(&mut self, a: Value) -> Value {
+        self.unary(Operation::Tan1, a)
+    }
+
+    /// Arctangent of `a` / `b`, per component
+    pub fn atan2(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Atan2, a, b)
+    }
+
+    pub fn sqrt(&mut self, a: Value) -> Value {
+        self.unary(Operation::Sqrt1, a)
+    }
+
+    /// `a` to the power of `b`, per component
+    pub fn pow(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Pow2, a, b)
+    }
+
+    pub fn exp(&mut self, a: Value) -> Value {
+        self.unary(Operation::Exp1, a)
+    }
+
+    pub fn ln(&mut self, a: Value) -> Value {
+        self.unary(Operation::Ln1, a)
+    }
+
+    pub fn abs(&mut self, a: Value) -> Value {
+        self.unary(Operation::Abs1, a)
+    }
+
+    pub fn floor(&mut self, a: Value) -> Value {
+        self.unary(Operation::Floor1, a)
+    }
+
+    pub fn ceil(&mut self, a: Value) -> Value {
+        self.unary(Operation::Ceil1, a)
+    }
+
+    pub fn fract(&mut self, a: Value) -> Value {
+        self.unary(Operation::Fract1, a)
+    }
+
+    /// `a` modulo `b`, with the sign of `b`
+    pub fn modulo(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Mod2, a, b)
+    }
+
+    pub fn min(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Min2, a, b)
+    }
+
+    pub fn max(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Max2, a, b)
+    }
+
+    /// `a + (b - a) * t.x`
+    pub fn lerp(&mut self, a: Value, b: Value, t: Value) -> Value {
+        self.instruction(Operation::Lerp3, [a, b, t])
+    }
+
+    /// `a + (b - a) * t`, per component
+    pub fn mix(&mut self, a: Value, b: Value, t: Value) -> Value {
+        self.instruction(Operation::Mix3, [a, b, t])
+    }
+
+    /// Goes smoothly from 0 to 1 as `a` goes from `edge0` to `edge1`
+    pub fn smoothstep(&mut self, a: Value, edge0: Value, edge1: Value) -> Value {
+        self.instruction(Operation::Smoothstep3, [a, edge0, edge1])
+    }
+
+    /// 0 where `a` is below `edge`, else 1
+    pub fn step(&mut self, a: Value, edge: Value) -> Value {
+        self.binary(Operation::Step2, a, edge)
+    }
+
+    /// 1 where `a < b`, else 0
+    pub fn less(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Less2, a, b)
+    }
+
+    /// 1 where `a` and `b` are equal, give or take [`EQUAL_EPSILON`], else 0
+    pub fn equal(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Equal2, a, b)
+    }
+
+    /// 1 where `a > b`, else 0
+    pub fn greater(&mut self, a: Value, b: Value) -> Value {
+        self.binary(Operation::Greater2, a, b)
+    }
+
+    /// `a` if `condition.x` isn't zero, else `b`
+    pub fn choose(&mut self, a: Value, b: Value, condition: Value) -> Value {
+        self.instruction(Operation::Choose3, [a, b, condition])
+    }
+
+    pub fn ease_in_quad(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseInQuad1, t)
+    }
+
+    pub fn ease_out_quad(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseOutQuad1, t)
+    }
+
+    pub fn ease_in_out_quad(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseInOutQuad1, t)
+    }
+
+    pub fn ease_in_cubic(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseInCubic1, t)
+    }
+
+    pub fn ease_out_cubic(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseOutCubic1, t)
+    }
+
+    pub fn ease_in_out_cubic(&mut self, t: Value) -> Value {
+        self.unary(Operation::EaseInOutCubic1, t)
+    }
+
+    pub fn bounce(&mut self, t: Value) -> Value {
+        self.unary(Operation::Bounce1, t)
+    }
+
+    /// Like CSS `cubic-bezier()`, with control points `p1` and `p2`
+    pub fn cubic_bezier_ease(&mut self, t: Value, p1: Value, p2: Value) -> Value {
+        self.instruction(Operation::CubicBezierEase3, [t, p1, p2])
+    }
+
+    /// Oscillates around 1 with an angular `frequency.x` and `damping.y`
+    pub fn spring(&mut self, t: Value, frequency_damping: Value) -> Value {
+        self.binary(Operation::Spring2, t, frequency_damping)
+    }
+
+    /// `a` mapped by the affine matrix whose columns are `x_axis`, `y_axis` and `origin`
+    pub fn transform(&mut self, a: Value, x_axis: Value, y_axis: Value, origin: Value) -> Value {
+        let linear = self.instruction(Operation::Adjusted3, [x_axis, y_axis, a]);
+        self.add(linear, origin)
+    }
+
+    /// Copies the columns of an affine matrix to consecutive stack entries,
+    /// as [`Self::affine`] reads them
+    pub fn matrix(&mut self, x_axis: Value, y_axis: Value, origin: Value) -> Value {
+        let first = self.instructions.len();
+        for column in [x_axis, y_axis, origin] {
+            // not deduplicated, so that the copies stay together
+            self.instructions.push((Operation::Select2, [column; 3]));
+        }
+        Value(Slot::Computed(first))
+    }
+
+    /// `a` mapped by a [`Self::matrix`]
+    pub fn affine(&mut self, a: Value, matrix: Value) -> Value {
+        self.binary(Operation::Affine2, a, matrix)
+    }
+
+    pub fn output(&mut self, name: &str, value: Value) {
+        self.outputs.push((name.into(), value));
+    }
+
+    /// Animates an argument; keyframes must be ordered by time
+    pub fn track(&mut self, argument: Value, keyframes: &[Keyframe]) {
+        self.tracks.push(Track {
+            argument,
+            keyframes: keyframes.into(),
+        });
+    }
+
+    /// Sets the duration, loop mode and frame rate of the animation;
+    /// its size is set with [`Self::metadata`] and [`WIDTH_KEY`] / [`HEIGHT_KEY`]
+    pub fn playback(&mut self, playback: Playback) {
+        self.playback = Some(playback);
+    }
+
+    /// Sets a metadata entry, such as [`TITLE_KEY`]
+    pub fn metadata(&mut self, key: &str, value: &str) {
+        match self.metadata.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.into(),
+            None => self.metadata.push((key.into(), value.into())),
+        }
+    }
+
+    pub fn rendering_step(&mut self, step: SymbolicStep) {
+        self.rendering_steps.push(step);
+    }
+
+    /// Sets the blend mode of the next clips, strokes, gradients and solids
+    pub fn blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn clip(&mut self, path: &[PathStep<Value>], background: &[Triangle<Value>], rule: FillRule) {
+        self.rendering_step(RenderingStep::Clip(path.into(), background.into(), rule, self.blend_mode));
+    }
+
+    pub fn stroke(&mut self, path: &[PathStep<Value>], stroker: Stroker<Value>) {
+        self.rendering_step(RenderingStep::Stroke(path.into(), stroker, self.blend_mode));
+    }
+
+    pub fn gradient(&mut self, path: &[PathStep<Value>], gradient: Gradient<Value>, rule: FillRule) {
+        self.rendering_step(RenderingStep::Gradient(path.into(), gradient, rule, self.blend_mode));
+    }
+
+    pub fn solid(&mut self, path: &[PathStep<Value>], color: [Value; 2], rule: FillRule) {
+        self.rendering_step(RenderingStep::Solid(path.into(), color, rule, self.blend_mode));
+    }
+
+    /// Draws the rendering steps of `children` in a separate layer,
+    /// which is then blended with an opacity (X of `opacity`)
+    pub fn group<F: FnOnce(&mut Self)>(&mut self, opacity: Value, mode: BlendMode, children: F) {
+        let group = self.rendering_steps.len();
+        self.rendering_step(RenderingStep::Group(0, opacity, mode));
+        children(self);
+
+        let length = self.rendering_steps.len() - group - 1;
+        self.rendering_steps[group] = RenderingStep::Group(length, opacity, mode);
+    }
+
+    /// Limits the rendering steps of `children` to the inside of a path
+    pub fn clipped<F: FnOnce(&mut Self)>(&mut self, path: &[PathStep<Value>], rule: FillRule, children: F) {
+        self.rendering_step(RenderingStep::PushClip(path.into(), rule));
+        children(self);
+        self.rendering_step(RenderingStep::PopClip);
+    }
+
+    /// Stack address of a value, given the current arguments
+    pub fn address(&self, value: Value) -> Address {
+        match value.0 {
+            Slot::Argument(i) => i,
+            Slot::Computed(i) => self.arguments.len() + i,
+        }
+    }
+
+    /// Resolves every value to its stack address
+    pub fn build(&self) -> Document {
+        let address = |value| self.address(value);
+        let path = |path: &Vec<PathStep<Value>>| path.iter().map(|s| s.map(address)).collect();
+
+        let instructions = self.instructions.iter().map(|(operation, operands)| {
+            let used = operation.number_of_operands() as usize;
+            let mut operands = operands.map(address);
+            operands[used..].fill(0);
+            Instruction {
+                operation: *operation,
+                operands,
+            }
+        });
+
+        let outputs = self.outputs.iter().map(|(name, value)| Output {
+            name: Some(name.clone()),
+            address: address(*value),
+        });
+
+        let rendering_steps = self.rendering_steps.iter().map(|step| match step {
+            RenderingStep::Clip(p, background, rule, mode) => {
+                RenderingStep::Clip(path(p), background.iter().map(|t| t.map(address)).collect(), *rule, *mode)
+            },
+            RenderingStep::Stroke(p, stroker, mode) => RenderingStep::Stroke(path(p), stroker.map(address), *mode),
+            RenderingStep::Gradient(p, gradient, rule, mode) => {
+                RenderingStep::Gradient(path(p), gradient.clone().map(address), *rule, *mode)
+            },
+            RenderingStep::Solid(p, color, rule, mode) => RenderingStep::Solid(path(p), color.map(address), *rule, *mode),
+            RenderingStep::Group(length, opacity, mode) => RenderingStep::Group(*length, address(*opacity), *mode),
+            RenderingStep::PushClip(p, rule) => RenderingStep::PushClip(path(p), *rule),
+            RenderingStep::PopClip => RenderingStep::PopClip,
+        });
+
+        Document {
+            arguments: self.arguments.clone(),
+            instructions: instructions.collect(),
+            outputs: outputs.collect(),
+            tracks: self.tracks.iter().map(|t| t.clone().map(address)).collect(),
+            playback: self.playback,
+            metadata: self.metadata.clone(),
+            rendering_steps: rendering_steps.collect(),
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.build().serialize()
+    }
+}
+
+/// A tree of operations over values and constants
+///
+/// Expressions are cheap to clone: subtrees are shared.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr(Rc<Node>);
+
+#[derive(Debug, PartialEq)]
+enum Node {
+    Value(Value),
+    Constant(Couple),
+    Operation(Operation, [Expr; 3]),
+}
+
+impl Expr {
+    /// Builds an operation; operands which it doesn't use are ignored.
+    pub fn operation(operation: Operation, operands: [Expr; 3]) -> Self {
+        Self(Rc::new(Node::Operation(operation, operands)))
+    }
+
+    fn unary(self, operation: Operation) -> Self {
+        Self::operation(operation, [self.clone(), self.clone(), self])
+    }
+
+    fn binary<B: Into<Expr>>(self, operation: Operation, b: B) -> Self {
+        Self::operation(operation, [self.clone(), b.into(), self])
+    }
+
+    fn ternary<B: Into<Expr>, C: Into<Expr>>(self, operation: Operation, b: B, c: C) -> Self {
+        Self::operation(operation, [self, b.into(), c.into()])
+    }
+
+    pub fn polar(self) -> Self {
+        self.unary(Operation::Polar1)
+    }
+
+    pub fn cartesian(self) -> Self {
+        self.unary(Operation::Cartesian1)
+    }
+
+    pub fn swap(self) -> Self {
+        self.unary(Operation::Swap1)
+    }
+
+    /// Cartesian coordinates of `self`, added to `origin`
+    pub fn cartesian_from<B: Into<Expr>>(self, origin: B) -> Self {
+        self.binary(Operation::Cartesian2, origin)
+    }
+
+    /// X of `self` and Y of `b`
+    pub fn select<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Select2, b)
+    }
+
+    /// X of `self` and X of `b`
+    pub fn each_x<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::EachX2, b)
+    }
+
+    /// Y of `self` and Y of `b`
+    pub fn each_y<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::EachY2, b)
+    }
+
+    /// `(1, 0)` if `self` is inside the rectangle, else `(0, 1)`
+    pub fn inside<B: Into<Expr>, C: Into<Expr>>(self, min: B, max: C) -> Self {
+        self.ternary(Operation::Inside3, min, max)
+    }
+
+    /// `self * weights.x + b * weights.y`
+    pub fn adjusted<B: Into<Expr>, C: Into<Expr>>(self, b: B, weights: C) -> Self {
+        self.ternary(Operation::Adjusted3, b, weights)
+    }
+
+    pub fn clamp<B: Into<Expr>, C: Into<Expr>>(self, min: B, max: C) -> Self {
+        self.ternary(Operation::Clamp3, min, max)
+    }
+
+    /// `self` rotated around `pivot` by `angle.x` radians
+    pub fn rotate<B: Into<Expr>, C: Into<Expr>>(self, pivot: B, angle: C) -> Self {
+        self.ternary(Operation::Rotate3, pivot, angle)
+    }
+
+    /// `self` scaled from `pivot` by `factors`
+    pub fn scale<B: Into<Expr>, C: Into<Expr>>(self, pivot: B, factors: C) -> Self {
+        self.ternary(Operation::Scale3, pivot, factors)
+    }
+
+    pub fn sin(self) -> Self {
+        self.unary(Operation::Sin1)
+    }
+
+    pub fn cos(self) -> Self {
+        self.unary(Operation::Cos1)
+    }
+
+    pub fn tan(self) -> Self {
+        self.unary(Operation::Tan1)
+    }
+
+    /// Arctangent of `self` / `b`, per component
+    pub fn atan2<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Atan2, b)
+    }
+
+    pub fn sqrt(self) -> Self {
+        self.unary(Operation::Sqrt1)
+    }
+
+    /// `self` to the power of `b`, per component
+    pub fn pow<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Pow2, b)
+    }
+
+    pub fn exp(self) -> Self {
+        self.unary(Operation::Exp1)
+    }
+
+    pub fn ln(self) -> Self {
+        self.unary(Operation::Ln1)
+    }
+
+    pub fn abs(self) -> Self {
+        self.unary(Operation::Abs1)
+    }
+
+    pub fn floor(self) -> Self {
+        self.unary(Operation::Floor1)
+    }
+
+    pub fn ceil(self) -> Self {
+        self.unary(Operation::Ceil1)
+    }
+
+    pub fn fract(self) -> Self {
+        self.unary(Operation::Fract1)
+    }
+
+    /// `self` modulo `b`, with the sign of `b`
+    pub fn modulo<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Mod2, b)
+    }
+
+    pub fn min<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Min2, b)
+    }
+
+    pub fn max<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Max2, b)
+    }
+
+    /// `self + (b - self) * t.x`
+    pub fn lerp<B: Into<Expr>, C: Into<Expr>>(self, b: B, t: C) -> Self {
+        self.ternary(Operation::Lerp3, b, t)
+    }
+
+    /// `self + (b - self) * t`, per component
+    pub fn mix<B: Into<Expr>, C: Into<Expr>>(self, b: B, t: C) -> Self {
+        self.ternary(Operation::Mix3, b, t)
+    }
+
+    /// Goes smoothly from 0 to 1 as `self` goes from `edge0` to `edge1`
+    pub fn smoothstep<B: Into<Expr>, C: Into<Expr>>(self, edge0: B, edge1: C) -> Self {
+        self.ternary(Operation::Smoothstep3, edge0, edge1)
+    }
+
+    /// 0 where `self` is below `edge`, else 1
+    pub fn step<B: Into<Expr>>(self, edge: B) -> Self {
+        self.binary(Operation::Step2, edge)
+    }
+
+    /// 1 where `self < b`, else 0
+    pub fn less<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Less2, b)
+    }
+
+    /// 1 where `self` and `b` are equal, give or take [`EQUAL_EPSILON`], else 0
+    pub fn equal<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Equal2, b)
+    }
+
+    /// 1 where `self > b`, else 0
+    pub fn greater<B: Into<Expr>>(self, b: B) -> Self {
+        self.binary(Operation::Greater2, b)
+    }
+
+    /// `self` if `condition.x` isn't zero, else `b`
+    pub fn choose<B: Into<Expr>, C: Into<Expr>>(self, b: B, condition: C) -> Self {
+        self.ternary(Operation::Choose3, b, condition)
+    }
+
+    pub fn ease_in_quad(self) -> Self {
+        self.unary(Operation::EaseInQuad1)
+    }
+
+    pub fn ease_out_quad(self) -> Self {
+        self.unary(Operation::EaseOutQuad1)
+    }
+
+    pub fn ease_in_out_quad(self) -> Self {
+        self.unary(Operation::EaseInOutQuad1)
+    }
+
+    pub fn ease_in_cubic(self) -> Self {
+        self.unary(Operation::EaseInCubic1)
+    }
+
+    pub fn ease_out_cubic(self) -> Self {
+        self.unary(Operation::EaseOutCubic1)
+    }
+
+    pub fn ease_in_out_cubic(self) -> Self {
+        self.unary(Operation::EaseInOutCubic1)
+    }
+
+    pub fn bounce(self) -> Self {
+        self.unary(Operation::Bounce1)
+    }
+
+    /// Like CSS `cubic-bezier()`, with control points `p1` and `p2`
+    pub fn cubic_bezier_ease<B: Into<Expr>, C: Into<Expr>>(self, p1: B, p2: C) -> Self {
+        self.ternary(Operation::CubicBezierEase3, p1, p2)
+    }
+
+    /// Oscillates around 1 with an angular `frequency.x` and `damping.y`
+    pub fn spring<B: Into<Expr>>(self, frequency_damping: B) -> Self {
+        self.binary(Operation::Spring2, frequency_damping)
+    }
+
+    /// `self` mapped by the affine matrix whose columns are `x_axis`, `y_axis` and `origin`
+    pub fn transform<B: Into<Expr>, C: Into<Expr>, D: Into<Expr>>(self, x_axis: B, y_axis: C, origin: D) -> Self {
+        Self::operation(Operation::Adjusted3, [x_axis.into(), y_axis.into(), self]) + origin
+    }
+}
+
+impl From<Value> for Expr {
+    fn from(value: Value) -> Self {
+        Self(Rc::new(Node::Value(value)))
+    }
+}
+
+impl From<Couple> for Expr {
+    fn from(couple: Couple) -> Self {
+        Self(Rc::new(Node::Constant(couple)))
+    }
+}
+
+impl From<&Expr> for Expr {
+    fn from(expr: &Expr) -> Self {
+        expr.clone()
+    }
+}
+
+impl Value {
+    pub fn polar(self) -> Expr {
+        Expr::from(self).polar()
+    }
+
+    pub fn cartesian(self) -> Expr {
+        Expr::from(self).cartesian()
+    }
+
+    pub fn swap(self) -> Expr {
+        Expr::from(self).swap()
+    }
+
+    pub fn cartesian_from<B: Into<Expr>>(self, origin: B) -> Expr {
+        Expr::from(self).cartesian_from(origin)
+    }
+
+    pub fn select<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).select(b)
+    }
+
+    pub fn each_x<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).each_x(b)
+    }
+
+    pub fn each_y<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).each_y(b)
+    }
+
+    pub fn inside<B: Into<Expr>, C: Into<Expr>>(self, min: B, max: C) -> Expr {
+        Expr::from(self).inside(min, max)
+    }
+
+    pub fn adjusted<B: Into<Expr>, C: Into<Expr>>(self, b: B, weights: C) -> Expr {
+        Expr::from(self).adjusted(b, weights)
+    }
+
+    pub fn clamp<B: Into<Expr>, C: Into<Expr>>(self, min: B, max: C) -> Expr {
+        Expr::from(self).clamp(min, max)
+    }
+
+    pub fn rotate<B: Into<Expr>, C: Into<Expr>>(self, pivot: B, angle: C) -> Expr {
+        Expr::from(self).rotate(pivot, angle)
+    }
+
+    pub fn scale<B: Into<Expr>, C: Into<Expr>>(self, pivot: B, factors: C) -> Expr {
+        Expr::from(self).scale(pivot, factors)
+    }
+
+    pub fn sin(self) -> Expr {
+        Expr::from(self).sin()
+    }
+
+    pub fn cos(self) -> Expr {
+        Expr::from(self).cos()
+    }
+
+    pub fn tan(self) -> Expr {
+        Expr::from(self).tan()
+    }
+
+    pub fn atan2<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).atan2(b)
+    }
+
+    pub fn sqrt(self) -> Expr {
+        Expr::from(self).sqrt()
+    }
+
+    pub fn pow<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).pow(b)
+    }
+
+    pub fn exp(self) -> Expr {
+        Expr::from(self).exp()
+    }
+
+    pub fn ln(self) -> Expr {
+        Expr::from(self).ln()
+    }
+
+    pub fn abs(self) -> Expr {
+        Expr::from(self).abs()
+    }
+
+    pub fn floor(self) -> Expr {
+        Expr::from(self).floor()
+    }
+
+    pub fn ceil(self) -> Expr {
+        Expr::from(self).ceil()
+    }
+
+    pub fn fract(self) -> Expr {
+        Expr::from(self).fract()
+    }
+
+    pub fn modulo<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).modulo(b)
+    }
+
+    pub fn min<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).min(b)
+    }
+
+    pub fn max<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).max(b)
+    }
+
+    pub fn lerp<B: Into<Expr>, C: Into<Expr>>(self, b: B, t: C) -> Expr {
+        Expr::from(self).lerp(b, t)
+    }
+
+    pub fn mix<B: Into<Expr>, C: Into<Expr>>(self, b: B, t: C) -> Expr {
+        Expr::from(self).mix(b, t)
+    }
+
+    pub fn smoothstep<B: Into<Expr>, C: Into<Expr>>(self, edge0: B, edge1: C) -> Expr {
+        Expr::from(self).smoothstep(edge0, edge1)
+    }
+
+    pub fn step<B: Into<Expr>>(self, edge: B) -> Expr {
+        Expr::from(self).step(edge)
+    }
+
+    pub fn less<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).less(b)
+    }
+
+    pub fn equal<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).equal(b)
+    }
+
+    pub fn greater<B: Into<Expr>>(self, b: B) -> Expr {
+        Expr::from(self).greater(b)
+    }
+
+    pub fn choose<B: Into<Expr>, C: Into<Expr>>(self, b: B, condition: C) -> Expr {
+        Expr::from(self).choose(b, condition)
+    }
+
+    pub fn ease_in_quad(self) -> Expr {
+        Expr::from(self).ease_in_quad()
+    }
+
+    pub fn ease_out_quad(self) -> Expr {
+        Expr::from(self).ease_out_quad()
+    }
+
+    pub fn ease_in_out_quad(self) -> Expr {
+        Expr::from(self).ease_in_out_quad()
+    }
+
+    pub fn ease_in_cubic(self) -> Expr {
+        Expr::from(self).ease_in_cubic()
+    }
+
+    pub fn ease_out_cubic(self) -> Expr {
+        Expr::from(self).ease_out_cubic()
+    }
+
+    pub fn ease_in_out_cubic(self) -> Expr {
+        Expr::from(self).ease_in_out_cubic()
+    }
+
+    pub fn bounce(self) -> Expr {
+        Expr::from(self).bounce()
+    }
+
+    pub fn cubic_bezier_ease<B: Into<Expr>, C: Into<Expr>>(self, p1: B, p2: C) -> Expr {
+        Expr::from(self).cubic_bezier_ease(p1, p2)
+    }
+
+    pub fn spring<B: Into<Expr>>(self, frequency_damping: B) -> Expr {
+        Expr::from(self).spring(frequency_damping)
+    }
+
+    pub fn transform<B: Into<Expr>, C: Into<Expr>, D: Into<Expr>>(self, x_axis: B, y_axis: C, origin: D) -> Expr {
+        Expr::from(self).transform(x_axis, y_axis, origin)
+    }
+}
+
+macro_rules! operator {
+    ($trait:ident, $method:ident, $operation:ident) => {
+        impl<R: Into<Expr>> ops::$trait<R> for Expr {
+            type Output = Expr;
+
+            fn $method(self, rhs: R) -> Expr {
+                self.binary(Operation::$operation, rhs)
+            }
+        }
+
+        impl<R: Into<Expr>> ops::$trait<R> for &Expr {
+            type Output = Expr;
+
+            fn $method(self, rhs: R) -> Expr {
+                self.clone().binary(Operation::$operation, rhs)
+            }
+        }
+
+        impl<R: Into<Expr>> ops::$trait<R> for Value {
+            type Output = Expr;
+
+            fn $method(self, rhs: R) -> Expr {
+                Expr::from(self).binary(Operation::$operation, rhs)
+            }
+        }
+    }
+}
+
+operator!(Add, add, Add2);
+operator!(Sub, sub, Subtract2);
+operator!(Mul, mul, Multiply2);
+operator!(Div, div, Divide2);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_expressions_are_evaluated_once() {
+        let mut b = ProgramBuilder::new();
+        let mut e = Expr::from(b.arg("x", Couple::new(1.0, 1.0)));
+        for _ in 0..64 {
+            e = &e + &e;
+        }
+
+        b.eval(&e);
+        assert_eq!(b.instructions.len(), 64);
+
+        // evaluating it again reuses the instructions
+        b.eval(e);
+        assert_eq!(b.instructions.len(), 64);
+    }
+}
@@ -1,4 +1,4 @@
-use core::{str::from_utf8, cmp::Ordering};
+use core::{str::from_utf8, cmp::Ordering, fmt};
 use alloc::vec::Vec;
 
 #[allow(unused_imports)]
@@ -10,6 +10,23 @@ pub type ColorAddress = [Address; 2];
 pub type Couple = vek::vec::repr_c::vec2::Vec2<Float>;
 pub const C_ZERO: Couple = Couple::new(0.0, 0.0);
 
+/// Largest difference between two components which `Equal2` considers equal
+pub const EQUAL_EPSILON: Float = 0.00001;
+
+/// Name of the argument which [`SerializedProgram::animate`] sets to (time, 0), in seconds
+pub const TIME_ARGUMENT: &str = "time";
+
+// well-known metadata keys
+pub const TITLE_KEY: &str = "title";
+pub const AUTHOR_KEY: &str = "author";
+pub const LICENSE_KEY: &str = "license";
+/// `min_x min_y width height`, separated by spaces
+pub const VIEW_BOX_KEY: &str = "view-box";
+/// intrinsic width in pixels
+pub const WIDTH_KEY: &str = "width";
+/// intrinsic height in pixels
+pub const HEIGHT_KEY: &str = "height";
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Argument<T> {
     pub name: Option<T>,
@@ -41,6 +58,104 @@ pub struct Output<T> {
     pub address: Address,
 }
 
+/// How the value of a keyframe changes until the next keyframe
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interpolation {
+    /// the value jumps to the next one
+    Step = 0,
+    Linear = 1,
+    /// eases in and out
+    Smooth = 2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Keyframe {
+    /// in seconds
+    pub time: Float,
+    pub value: Couple,
+    pub interpolation: Interpolation,
+}
+
+impl Keyframe {
+    /// Value at `time`, between this keyframe and the next one
+    pub fn interpolate(&self, next: &Keyframe, time: Float) -> Couple {
+        let duration = next.time - self.time;
+        let t = match duration > 0.0 {
+            true => ((time - self.time) / duration).clamp(0.0, 1.0),
+            false => 1.0,
+        };
+        let t = match self.interpolation {
+            Interpolation::Step => step(t, 1.0),
+            Interpolation::Linear => t,
+            Interpolation::Smooth => smoothstep(t, 0.0, 1.0),
+        };
+        self.value + (next.value - self.value) * t
+    }
+}
+
+/// Keyframes which set an argument over time
+#[derive(Debug, Clone, PartialEq)]
+pub struct Track<A = Address> {
+    pub argument: A,
+    /// Ordered by time
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl<A> Track<A> {
+    pub fn map<B, F: FnOnce(A) -> B>(self, f: F) -> Track<B> {
+        Track {
+            argument: f(self.argument),
+            keyframes: self.keyframes,
+        }
+    }
+}
+
+/// What happens once an animation reaches its duration
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopMode {
+    /// the last frame stays
+    Once = 0,
+    /// starts over
+    Loop = 1,
+    /// plays backwards, then forwards again
+    PingPong = 2,
+}
+
+/// How an animated program is meant to be played
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Playback {
+    /// in seconds
+    pub duration: Float,
+    pub loop_mode: LoopMode,
+    /// nominal frame rate
+    pub fps: Float,
+}
+
+impl Playback {
+    /// Played once at 60 fps
+    pub fn new(duration: Float) -> Self {
+        Self {
+            duration,
+            loop_mode: LoopMode::Once,
+            fps: 60.0,
+        }
+    }
+
+    /// Animation time after `elapsed` seconds of playback
+    pub fn time_at(&self, elapsed: Float) -> Float {
+        let duration = self.duration;
+        if duration <= 0.0 {
+            return 0.0;
+        }
+
+        match self.loop_mode {
+            LoopMode::Once => elapsed.clamp(0.0, duration),
+            LoopMode::Loop => modulo(elapsed, duration),
+            LoopMode::PingPong => duration - (modulo(elapsed, 2.0 * duration) - duration).abs(),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Operation {
     Add2,       // add two couples
@@ -57,6 +172,41 @@ pub enum Operation {
     Swap1,      // swap X and Y
     Adjusted3,  // = a * c.x + b * c.y
     Clamp3,     // op1 clamped (op2 = min; op3 = max)
+    Rotate3,    // op1 rotated around op2 by op3.x radians
+    Scale3,     // op1 scaled from op2 by op3
+    Sin1,       // sine of each component
+    Cos1,       // cosine of each component
+    Tan1,       // tangent of each component
+    Atan2,      // arctangent of op1 / op2, per component
+    Sqrt1,      // square root of each component
+    Pow2,       // op1 to the power of op2, per component
+    Exp1,       // e to the power of each component
+    Ln1,        // natural logarithm of each component
+    Abs1,       // absolute value of each component
+    Floor1,     // largest integer below each component
+    Ceil1,      // smallest integer above each component
+    Fract1,     // op1 minus its floor
+    Mod2,       // op1 modulo op2, with the sign of op2
+    Min2,       // smallest of op1 and op2, per component
+    Max2,       // largest of op1 and op2, per component
+    Lerp3,      // op1 + (op2 - op1) * op3.x
+    Mix3,       // op1 + (op2 - op1) * op3, per component
+    Smoothstep3, // smooth transition from 0 to 1 as op1 goes from op2 to op3
+    Step2,      // 0 where op1 is below op2, else 1
+    Less2,      // 1 where op1 < op2, else 0
+    Equal2,     // 1 where op1 and op2 differ by at most EQUAL_EPSILON, else 0
+    Greater2,   // 1 where op1 > op2, else 0
+    Choose3,    // op1 if op3.x isn't zero, else op2
+    EaseInQuad1, // t * t, per component
+    EaseOutQuad1, // 1 - (1 - t) * (1 - t), per component
+    EaseInOutQuad1, // ease in, then out, per component
+    EaseInCubic1, // t * t * t, per component
+    EaseOutCubic1, // 1 - (1 - t)^3, per component
+    EaseInOutCubic1, // ease in, then out, per component
+    CubicBezierEase3, // CSS cubic-bezier(op2, op3), per component
+    Spring2,    // oscillates around 1, per component (op2 = angular frequency, damping)
+    Bounce1,    // bounces towards 1, per component
+    Affine2,    // op1 mapped by the 2x3 matrix in the three couples from op2
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -74,7 +224,7 @@ impl Instruction {
     }
 }
 
-const OPERATIONS: [Operation; 14] = [
+const OPERATIONS: [Operation; 49] = [
     Operation::Add2,
     Operation::Subtract2,
     Operation::Multiply2,
@@ -89,9 +239,48 @@ const OPERATIONS: [Operation; 14] = [
     Operation::Swap1,
     Operation::Adjusted3,
     Operation::Clamp3,
+    Operation::Rotate3,
+    Operation::Scale3,
+    Operation::Sin1,
+    Operation::Cos1,
+    Operation::Tan1,
+    Operation::Atan2,
+    Operation::Sqrt1,
+    Operation::Pow2,
+    Operation::Exp1,
+    Operation::Ln1,
+    Operation::Abs1,
+    Operation::Floor1,
+    Operation::Ceil1,
+    Operation::Fract1,
+    Operation::Mod2,
+    Operation::Min2,
+    Operation::Max2,
+    Operation::Lerp3,
+    Operation::Mix3,
+    Operation::Smoothstep3,
+    Operation::Step2,
+    Operation::Less2,
+    Operation::Equal2,
+    Operation::Greater2,
+    Operation::Choose3,
+    Operation::EaseInQuad1,
+    Operation::EaseOutQuad1,
+    Operation::EaseInOutQuad1,
+    Operation::EaseInCubic1,
+    Operation::EaseOutCubic1,
+    Operation::EaseInOutCubic1,
+    Operation::CubicBezierEase3,
+    Operation::Spring2,
+    Operation::Bounce1,
+    Operation::Affine2,
 ];
 
 impl Operation {
+    pub fn from_opcode(opcode: u32) -> Option<Self> {
+        OPERATIONS.get(opcode as usize).copied()
+    }
+
     pub fn opcode(self) -> u32 {
         match self {
             Operation::Add2 => 0x0,
@@ -108,6 +297,41 @@ impl Operation {
             Operation::Swap1 => 0xB,
             Operation::Adjusted3 => 0xC,
             Operation::Clamp3 => 0xD,
+            Operation::Rotate3 => 0xE,
+            Operation::Scale3 => 0xF,
+            Operation::Sin1 => 0x10,
+            Operation::Cos1 => 0x11,
+            Operation::Tan1 => 0x12,
+            Operation::Atan2 => 0x13,
+            Operation::Sqrt1 => 0x14,
+            Operation::Pow2 => 0x15,
+            Operation::Exp1 => 0x16,
+            Operation::Ln1 => 0x17,
+            Operation::Abs1 => 0x18,
+            Operation::Floor1 => 0x19,
+            Operation::Ceil1 => 0x1A,
+            Operation::Fract1 => 0x1B,
+            Operation::Mod2 => 0x1C,
+            Operation::Min2 => 0x1D,
+            Operation::Max2 => 0x1E,
+            Operation::Lerp3 => 0x1F,
+            Operation::Mix3 => 0x20,
+            Operation::Smoothstep3 => 0x21,
+            Operation::Step2 => 0x22,
+            Operation::Less2 => 0x23,
+            Operation::Equal2 => 0x24,
+            Operation::Greater2 => 0x25,
+            Operation::Choose3 => 0x26,
+            Operation::EaseInQuad1 => 0x27,
+            Operation::EaseOutQuad1 => 0x28,
+            Operation::EaseInOutQuad1 => 0x29,
+            Operation::EaseInCubic1 => 0x2A,
+            Operation::EaseOutCubic1 => 0x2B,
+            Operation::EaseInOutCubic1 => 0x2C,
+            Operation::CubicBezierEase3 => 0x2D,
+            Operation::Spring2 => 0x2E,
+            Operation::Bounce1 => 0x2F,
+            Operation::Affine2 => 0x30,
         }
     }
 
@@ -127,6 +351,41 @@ impl Operation {
             Operation::Swap1 => 1,
             Operation::Adjusted3 => 3,
             Operation::Clamp3 => 3,
+            Operation::Rotate3 => 3,
+            Operation::Scale3 => 3,
+            Operation::Sin1 => 1,
+            Operation::Cos1 => 1,
+            Operation::Tan1 => 1,
+            Operation::Atan2 => 2,
+            Operation::Sqrt1 => 1,
+            Operation::Pow2 => 2,
+            Operation::Exp1 => 1,
+            Operation::Ln1 => 1,
+            Operation::Abs1 => 1,
+            Operation::Floor1 => 1,
+            Operation::Ceil1 => 1,
+            Operation::Fract1 => 1,
+            Operation::Mod2 => 2,
+            Operation::Min2 => 2,
+            Operation::Max2 => 2,
+            Operation::Lerp3 => 3,
+            Operation::Mix3 => 3,
+            Operation::Smoothstep3 => 3,
+            Operation::Step2 => 2,
+            Operation::Less2 => 2,
+            Operation::Equal2 => 2,
+            Operation::Greater2 => 2,
+            Operation::Choose3 => 3,
+            Operation::EaseInQuad1 => 1,
+            Operation::EaseOutQuad1 => 1,
+            Operation::EaseInOutQuad1 => 1,
+            Operation::EaseInCubic1 => 1,
+            Operation::EaseOutCubic1 => 1,
+            Operation::EaseInOutCubic1 => 1,
+            Operation::CubicBezierEase3 => 3,
+            Operation::Spring2 => 2,
+            Operation::Bounce1 => 1,
+            Operation::Affine2 => 2,
         }
     }
 
@@ -146,6 +405,41 @@ impl Operation {
             Operation::Swap1 => "Swap1",
             Operation::Adjusted3 => "Adjusted3",
             Operation::Clamp3 => "Clamp3",
+            Operation::Rotate3 => "Rotate3",
+            Operation::Scale3 => "Scale3",
+            Operation::Sin1 => "Sin1",
+            Operation::Cos1 => "Cos1",
+            Operation::Tan1 => "Tan1",
+            Operation::Atan2 => "Atan2",
+            Operation::Sqrt1 => "Sqrt1",
+            Operation::Pow2 => "Pow2",
+            Operation::Exp1 => "Exp1",
+            Operation::Ln1 => "Ln1",
+            Operation::Abs1 => "Abs1",
+            Operation::Floor1 => "Floor1",
+            Operation::Ceil1 => "Ceil1",
+            Operation::Fract1 => "Fract1",
+            Operation::Mod2 => "Mod2",
+            Operation::Min2 => "Min2",
+            Operation::Max2 => "Max2",
+            Operation::Lerp3 => "Lerp3",
+            Operation::Mix3 => "Mix3",
+            Operation::Smoothstep3 => "Smoothstep3",
+            Operation::Step2 => "Step2",
+            Operation::Less2 => "Less2",
+            Operation::Equal2 => "Equal2",
+            Operation::Greater2 => "Greater2",
+            Operation::Choose3 => "Choose3",
+            Operation::EaseInQuad1 => "EaseInQuad1",
+            Operation::EaseOutQuad1 => "EaseOutQuad1",
+            Operation::EaseInOutQuad1 => "EaseInOutQuad1",
+            Operation::EaseInCubic1 => "EaseInCubic1",
+            Operation::EaseOutCubic1 => "EaseOutCubic1",
+            Operation::EaseInOutCubic1 => "EaseInOutCubic1",
+            Operation::CubicBezierEase3 => "CubicBezierEase3",
+            Operation::Spring2 => "Spring2",
+            Operation::Bounce1 => "Bounce1",
+            Operation::Affine2 => "Affine2",
         }
     }
 }
@@ -155,18 +449,80 @@ fn cartesian1(a: Couple) -> (Float, Float) {
     (tmp.1 * a.y, (-tmp.0) * a.y)
 }
 
+fn modulo(a: Float, b: Float) -> Float {
+    a - b * (a / b).floor()
+}
+
+fn smoothstep(x: Float, edge0: Float, edge1: Float) -> Float {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn step(x: Float, edge: Float) -> Float {
+    mask(x >= edge)
+}
+
+fn mask(condition: bool) -> Float {
+    match condition {
+        true => 1.0,
+        false => 0.0,
+    }
+}
+
+/// Applies an easing function to both components, clamped to [0, 1]
+fn eased<F: Fn(Float) -> Float>(a: Couple, f: F) -> (Float, Float) {
+    (f(a.x.clamp(0.0, 1.0)), f(a.y.clamp(0.0, 1.0)))
+}
+
+/// Like CSS `cubic-bezier(p1.x, p1.y, p2.x, p2.y)`
+fn cubic_bezier_ease(t: Float, p1: Couple, p2: Couple) -> Float {
+    let bezier = |s: Float, a: Float, b: Float| {
+        let r = 1.0 - s;
+        3.0 * r * r * s * a + 3.0 * r * s * s * b + s * s * s
+    };
+
+    // X increases with s when the control points are within [0, 1]
+    let (x1, x2) = (p1.x.clamp(0.0, 1.0), p2.x.clamp(0.0, 1.0));
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..24 {
+        let s = (low + high) / 2.0;
+        match bezier(s, x1, x2) < t {
+            true => low = s,
+            false => high = s,
+        }
+    }
+
+    bezier((low + high) / 2.0, p1.y, p2.y)
+}
+
+fn bounce(t: Float) -> Float {
+    const N: Float = 7.5625;
+    const D: Float = 2.75;
+
+    let (t, offset) = match t {
+        t if t < 1.0 / D => (t, 0.0),
+        t if t < 2.0 / D => (t - 1.5 / D, 0.75),
+        t if t < 2.5 / D => (t - 2.25 / D, 0.9375),
+        t => (t - 2.625 / D, 0.984375),
+    };
+    N * t * t + offset
+}
+
 fn add2(a: Couple, b: Couple) -> (Float, Float) {
     (a.x + b.x, a.y + b.y)
 }
 
+/// Result of an instruction, unless it reads outside of `stack`
 fn compute(
     instruction: Instruction,
-    operands: [Couple; 3],
-) -> Couple {
+    stack: &[Couple],
+) -> Option<Couple> {
     use Operation::*;
 
-    let [a, b, c] = operands;
-    Couple::from(match instruction.operation {
+    let operand = |address: Address| stack.get(address).copied();
+    let [op1, op2, op3] = instruction.operands;
+    let [a, b, c] = [operand(op1)?, operand(op2)?, operand(op3)?];
+    Some(Couple::from(match instruction.operation {
         Add2 => add2(a, b),
         Subtract2 => (a.x - b.x, a.y - b.y),
         Multiply2 => (a.x * b.x, a.y * b.y),
@@ -195,90 +551,533 @@ fn compute(
         Swap1 => (a.y, a.x),
         Adjusted3 => (a.x * c.x + b.x * c.y, a.y * c.x + b.y * c.y),
         Clamp3 => (a.x.clamp(b.x, c.x), a.y.clamp(b.y, c.y)),
-    })
+        Rotate3 => {
+            // same direction as the angles of Polar1
+            let (sin, cos) = c.x.sin_cos();
+            let (x, y) = (a.x - b.x, a.y - b.y);
+            (b.x + x * cos + y * sin, b.y + y * cos - x * sin)
+        }
+        Scale3 => (b.x + (a.x - b.x) * c.x, b.y + (a.y - b.y) * c.y),
+        Sin1 => (a.x.sin(), a.y.sin()),
+        Cos1 => (a.x.cos(), a.y.cos()),
+        Tan1 => (a.x.tan(), a.y.tan()),
+        Atan2 => (a.x.atan2(b.x), a.y.atan2(b.y)),
+        Sqrt1 => (a.x.sqrt(), a.y.sqrt()),
+        Pow2 => (a.x.powf(b.x), a.y.powf(b.y)),
+        Exp1 => (a.x.exp(), a.y.exp()),
+        Ln1 => (a.x.ln(), a.y.ln()),
+        Abs1 => (a.x.abs(), a.y.abs()),
+        Floor1 => (a.x.floor(), a.y.floor()),
+        Ceil1 => (a.x.ceil(), a.y.ceil()),
+        Fract1 => (a.x - a.x.floor(), a.y - a.y.floor()),
+        Mod2 => (modulo(a.x, b.x), modulo(a.y, b.y)),
+        Min2 => (a.x.min(b.x), a.y.min(b.y)),
+        Max2 => (a.x.max(b.x), a.y.max(b.y)),
+        Lerp3 => (a.x + (b.x - a.x) * c.x, a.y + (b.y - a.y) * c.x),
+        Mix3 => (a.x + (b.x - a.x) * c.x, a.y + (b.y - a.y) * c.y),
+        Smoothstep3 => (smoothstep(a.x, b.x, c.x), smoothstep(a.y, b.y, c.y)),
+        Step2 => (step(a.x, b.x), step(a.y, b.y)),
+        Less2 => (mask(a.x < b.x), mask(a.y < b.y)),
+        Equal2 => (mask((a.x - b.x).abs() <= EQUAL_EPSILON), mask((a.y - b.y).abs() <= EQUAL_EPSILON)),
+        Greater2 => (mask(a.x > b.x), mask(a.y > b.y)),
+        Choose3 => match c.x != 0.0 {
+            true => (a.x, a.y),
+            false => (b.x, b.y),
+        },
+        EaseInQuad1 => eased(a, |t| t * t),
+        EaseOutQuad1 => eased(a, |t| 1.0 - (1.0 - t) * (1.0 - t)),
+        EaseInOutQuad1 => eased(a, |t| match t < 0.5 {
+            true => 2.0 * t * t,
+            false => 1.0 - (2.0 - 2.0 * t).powi(2) / 2.0,
+        }),
+        EaseInCubic1 => eased(a, |t| t * t * t),
+        EaseOutCubic1 => eased(a, |t| 1.0 - (1.0 - t).powi(3)),
+        EaseInOutCubic1 => eased(a, |t| match t < 0.5 {
+            true => 4.0 * t * t * t,
+            false => 1.0 - (2.0 - 2.0 * t).powi(3) / 2.0,
+        }),
+        CubicBezierEase3 => eased(a, |t| cubic_bezier_ease(t, b, c)),
+        Spring2 => eased(a, |t| 1.0 - (-b.y * t).exp() * (b.x * t).cos()),
+        Bounce1 => eased(a, bounce),
+        Affine2 => {
+            // columns: x axis (b), y axis and origin
+            let (y_axis, origin) = (op2.checked_add(1).and_then(operand)?, op2.checked_add(2).and_then(operand)?);
+            (a.x * b.x + a.y * y_axis.x + origin.x, a.x * b.y + a.y * y_axis.y + origin.y)
+        },
+    }))
+}
+
+// The following types are generic over the address type
+// so that builders can use symbolic handles before resolving
+// them to stack addresses with `map`.
+
+/// Shape of the ends of open strokes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineCap {
+    Butt = 0,
+    Round = 1,
+    Square = 2,
+}
+
+/// Shape of the corners of strokes
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineJoin {
+    Miter = 0,
+    Round = 1,
+    Bevel = 2,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Stroker {
-    pub pattern: Address,
+pub struct Stroker<A = Address> {
+    pub pattern: A,
     /// The stroke with is the addition of X and Y at this address
-    pub width: Address,
-    pub color: ColorAddress,
+    pub width: A,
+    pub color: [A; 2],
+    /// Whether the contour goes back to its first point
+    pub closed: bool,
+    pub cap: LineCap,
+    pub join: LineJoin,
+    /// Miter joins longer than `miter_limit * width` become bevel joins
+    pub miter_limit: Float,
+}
+
+impl<A> Stroker<A> {
+    /// A closed stroker with round caps and joins
+    pub fn new(pattern: A, width: A, color: [A; 2]) -> Self {
+        Self {
+            pattern,
+            width,
+            color,
+            closed: true,
+            cap: LineCap::Round,
+            join: LineJoin::Round,
+            miter_limit: 4.0,
+        }
+    }
+
+    pub fn map<B, F: FnMut(A) -> B>(self, mut f: F) -> Stroker<B> {
+        Stroker {
+            pattern: f(self.pattern),
+            width: f(self.width),
+            color: self.color.map(f),
+            closed: self.closed,
+            cap: self.cap,
+            join: self.join,
+            miter_limit: self.miter_limit,
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Triangle {
-    pub points: [Address; 3],
-    pub colors: [ColorAddress; 3],
+pub struct Triangle<A = Address> {
+    pub points: [A; 3],
+    pub colors: [[A; 2]; 3],
+}
+
+impl<A> Triangle<A> {
+    pub fn map<B, F: FnMut(A) -> B>(self, mut f: F) -> Triangle<B> {
+        Triangle {
+            points: self.points.map(&mut f),
+            colors: self.colors.map(|color| color.map(&mut f)),
+        }
+    }
+}
+
+/// How gradients map points to color stop offsets
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GradientKind {
+    /// points: start, end
+    Linear = 0,
+    /// points: center, radius (X + Y), focal point
+    Radial = 1,
+    /// points: center, start angle (X)
+    Conic = 2,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Arc {
-    pub start_point: Address,
-    pub center: Address,
-    pub deltas: Address,
+pub struct ColorStop<A = Address> {
+    /// The offset is the X of this address, from 0 to 1
+    pub offset: A,
+    pub color: [A; 2],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gradient<A = Address> {
+    pub kind: GradientKind,
+    /// Unused points should be the first one
+    pub points: [A; 3],
+    /// Ordered by offset
+    pub stops: Vec<ColorStop<A>>,
+}
+
+impl<A> Gradient<A> {
+    pub fn map<B, F: FnMut(A) -> B>(self, mut f: F) -> Gradient<B> {
+        Gradient {
+            kind: self.kind,
+            points: self.points.map(&mut f),
+            stops: self.stops.into_iter().map(|stop| ColorStop {
+                offset: f(stop.offset),
+                color: stop.color.map(&mut f),
+            }).collect(),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct CubicCurve {
-    pub points: [Address; 4],
+pub struct Arc<A = Address> {
+    pub start_point: A,
+    pub center: A,
+    pub deltas: A,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct QuadraticCurve {
-    pub points: [Address; 3],
+pub struct CubicCurve<A = Address> {
+    pub points: [A; 4],
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub struct Line {
-    pub points: [Address; 2],
+pub struct QuadraticCurve<A = Address> {
+    pub points: [A; 3],
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum PathStep {
-    Arc(Arc),
-    CubicCurve(CubicCurve),
-    QuadraticCurve(QuadraticCurve),
-    Line(Line),
+pub struct Line<A = Address> {
+    pub points: [A; 2],
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum RenderingStep<P, B> {
-    Clip(P, B),
-    Stroke(P, Stroker),
+pub enum PathStep<A = Address> {
+    Arc(Arc<A>),
+    CubicCurve(CubicCurve<A>),
+    QuadraticCurve(QuadraticCurve<A>),
+    Line(Line<A>),
+    /// Closes the current contour; following steps start another one
+    NewContour,
+}
+
+impl<A> PathStep<A> {
+    pub fn map<B, F: FnMut(A) -> B>(self, mut f: F) -> PathStep<B> {
+        match self {
+            PathStep::Arc(arc) => PathStep::Arc(Arc {
+                start_point: f(arc.start_point),
+                center: f(arc.center),
+                deltas: f(arc.deltas),
+            }),
+            PathStep::CubicCurve(curve) => PathStep::CubicCurve(CubicCurve { points: curve.points.map(f) }),
+            PathStep::QuadraticCurve(curve) => PathStep::QuadraticCurve(QuadraticCurve { points: curve.points.map(f) }),
+            PathStep::Line(line) => PathStep::Line(Line { points: line.points.map(f) }),
+            PathStep::NewContour => PathStep::NewContour,
+        }
+    }
+}
+
+/// Tells which points are inside of a path, given the number of times
+/// its contour winds around them
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum FillRule {
+    /// inside if the contour winds around at least once
+    #[default]
+    NonZero = 0,
+    /// inside if the contour winds around an odd number of times
+    EvenOdd = 1,
+}
+
+/// How a rendering step is composited with what's under it
+///
+/// The first modes blend colors, then draw over the destination;
+/// the others are Porter-Duff operators.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum BlendMode {
+    #[default]
+    SourceOver = 0,
+    Multiply = 1,
+    Screen = 2,
+    Overlay = 3,
+    Darken = 4,
+    Lighten = 5,
+    /// sum of both colors, clamped
+    Add = 6,
+    SourceIn = 7,
+    SourceOut = 8,
+    SourceAtop = 9,
+    DestinationIn = 10,
+    DestinationOut = 11,
+    Xor = 12,
+}
+
+const BLEND_MODES: [BlendMode; 13] = [
+    BlendMode::SourceOver,
+    BlendMode::Multiply,
+    BlendMode::Screen,
+    BlendMode::Overlay,
+    BlendMode::Darken,
+    BlendMode::Lighten,
+    BlendMode::Add,
+    BlendMode::SourceIn,
+    BlendMode::SourceOut,
+    BlendMode::SourceAtop,
+    BlendMode::DestinationIn,
+    BlendMode::DestinationOut,
+    BlendMode::Xor,
+];
+
+impl BlendMode {
+    pub fn from_index(index: u32) -> Option<Self> {
+        BLEND_MODES.get(index as usize).copied()
+    }
+
+    pub fn as_text(self) -> &'static str {
+        match self {
+            BlendMode::SourceOver => "source-over",
+            BlendMode::Multiply => "multiply",
+            BlendMode::Screen => "screen",
+            BlendMode::Overlay => "overlay",
+            BlendMode::Darken => "darken",
+            BlendMode::Lighten => "lighten",
+            BlendMode::Add => "add",
+            BlendMode::SourceIn => "source-in",
+            BlendMode::SourceOut => "source-out",
+            BlendMode::SourceAtop => "source-atop",
+            BlendMode::DestinationIn => "destination-in",
+            BlendMode::DestinationOut => "destination-out",
+            BlendMode::Xor => "xor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RenderingStep<P, B, A = Address> {
+    Clip(P, B, FillRule, BlendMode),
+    Stroke(P, Stroker<A>, BlendMode),
+    Gradient(P, Gradient<A>, FillRule, BlendMode),
+    /// Fills the path with one color
+    Solid(P, [A; 2], FillRule, BlendMode),
+    /// Draws the next N steps in a separate layer, which is then
+    /// composited with the opacity at X of this address
+    Group(usize, A, BlendMode),
+    /// Limits the following steps to the inside of the path,
+    /// and of previously pushed clip paths
+    PushClip(P, FillRule),
+    /// Removes the last pushed clip path
+    PopClip,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum RawRenderingStep {
-    Clip(usize, usize),
-    Stroke(usize, usize),
+    Clip(usize, usize, FillRule, BlendMode),
+    Stroke(usize, usize, BlendMode),
+    Gradient(usize, usize, FillRule, BlendMode),
+    Solid(usize, usize, FillRule, BlendMode),
+    Group(usize, Address, BlendMode),
+    PushClip(usize, FillRule),
+    PopClip,
 }
 
-use ParsingError::*;
+use ErrorKind::*;
 
-#[derive(Debug, Copy, Clone)]
-pub enum ParsingError {
+/// Sections of a railway file
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Section {
+    Header,
+    Arguments,
+    Instructions,
+    Outputs,
+    Tracks,
+    Keyframes,
+    Playback,
+    Metadata,
+    Triangles,
+    Arcs,
+    CubicCurves,
+    QuadraticCurves,
+    Lines,
+    Strokers,
+    Steps,
+    Paths,
+    TriangleIndexes,
+    Backgrounds,
+    Gradients,
+    ColorStops,
+    Colors,
+    RenderingSteps,
+    Strings,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErrorKind {
     NotARailwayFile,
     TooShort,
     ExcessBytes,
+    /// section length which doesn't fit its items
+    InvalidSectionLength,
     InvalidStepType,
     InvalidOperation,
     InvalidRenderingStep,
+    /// clip pop without a clip push before it, or push without a pop after it
+    UnbalancedClip,
+    /// clip pushed inside a group and popped outside of it, or the reverse
+    ClipCrossesGroup,
+    /// unknown value in a stroker's style fields
+    InvalidStroker,
+    InvalidFillRule,
+    InvalidBlendMode,
+    InvalidGradient,
+    InvalidInterpolation,
+    /// keyframes of a track which aren't ordered by time
+    UnsortedKeyframes,
+    InvalidLoopMode,
     InvalidName,
     NoArguments,
     InvalidIndex,
+    /// stack address which is out of the stack or not computed yet
+    InvalidAddress,
+}
+
+/// A parsing error and where it was found
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ParsingError {
+    pub kind: ErrorKind,
+    pub section: Option<Section>,
+    /// index of the faulty item in `section`
+    pub index: Option<usize>,
+    /// byte offset of the faulty data in the file
+    pub offset: Option<usize>,
+}
+
+impl ParsingError {
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            section: None,
+            index: None,
+            offset: None,
+        }
+    }
+
+    pub const fn item(kind: ErrorKind, section: Section, index: usize, offset: usize) -> Self {
+        Self {
+            kind,
+            section: Some(section),
+            index: Some(index),
+            offset: Some(offset),
+        }
+    }
+
+    /// Sets the section and item index, unless they're already known
+    pub fn in_item(mut self, section: Section, index: usize) -> Self {
+        if self.section.is_none() {
+            self.section = Some(section);
+            self.index = Some(index);
+        }
+        self
+    }
+
+    /// Sets the byte offset, unless it's already known
+    pub fn at(mut self, offset: usize) -> Self {
+        self.offset.get_or_insert(offset);
+        self
+    }
+}
+
+impl From<ErrorKind> for ParsingError {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
 }
 
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Section::Header => "header",
+            Section::Arguments => "arguments",
+            Section::Instructions => "instructions",
+            Section::Outputs => "outputs",
+            Section::Tracks => "tracks",
+            Section::Keyframes => "keyframes",
+            Section::Playback => "playback",
+            Section::Metadata => "metadata",
+            Section::Triangles => "triangles",
+            Section::Arcs => "arcs",
+            Section::CubicCurves => "cubic curves",
+            Section::QuadraticCurves => "quadratic curves",
+            Section::Lines => "lines",
+            Section::Strokers => "strokers",
+            Section::Steps => "steps",
+            Section::Paths => "paths",
+            Section::TriangleIndexes => "triangle indexes",
+            Section::Backgrounds => "backgrounds",
+            Section::Gradients => "gradients",
+            Section::ColorStops => "color stops",
+            Section::Colors => "colors",
+            Section::RenderingSteps => "rendering steps",
+            Section::Strings => "strings",
+        })
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            NotARailwayFile => "not a railway file",
+            TooShort => "unexpected end of file",
+            ExcessBytes => "unexpected bytes after the last section",
+            InvalidSectionLength => "invalid section length",
+            InvalidStepType => "invalid path step type",
+            InvalidOperation => "invalid operation",
+            InvalidRenderingStep => "invalid rendering step type",
+            UnbalancedClip => "clip push and pop don't match",
+            ClipCrossesGroup => "clip push and pop in different groups",
+            InvalidStroker => "invalid stroker style",
+            InvalidFillRule => "invalid fill rule",
+            InvalidBlendMode => "invalid blend mode",
+            InvalidGradient => "invalid gradient type",
+            InvalidInterpolation => "invalid interpolation",
+            UnsortedKeyframes => "keyframes aren't ordered by time",
+            InvalidLoopMode => "invalid loop mode",
+            InvalidName => "invalid string",
+            NoArguments => "no arguments",
+            InvalidIndex => "index out of bounds",
+            InvalidAddress => "invalid stack address",
+        })
+    }
+}
+
+impl fmt::Display for ParsingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(section) = self.section {
+            write!(f, " in {}", section)?;
+        }
+        if let Some(index) = self.index {
+            write!(f, " #{}", index)?;
+        }
+        if let Some(offset) = self.offset {
+            write!(f, " at byte {:#x}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParsingError {}
+
 pub type ParsingResult<T> = Result<T, ParsingError>;
 
-const MAGIC_BYTES: [u8; 4] = [b'R', b'W', b'Y', b'0'];
+const MAGIC_BYTES: [u8; 4] = [b'R', b'W', b'Y', b'1'];
+const LEGACY_MAGIC_BYTES: [u8; 4] = [b'R', b'W', b'Y', b'0'];
+
+/// Revision of the format written by [`serialize`]
+///
+/// Later revisions may add sections and item fields,
+/// which readers of earlier ones skip.
+pub const FORMAT_REVISION: u32 = 0;
 
 fn slice<'a>(bytes: &'a [u8], i: &mut usize, len: usize) -> ParsingResult<&'a [u8]> {
     let pos = *i;
     *i += len;
     match bytes.get(pos..*i) {
         Some(bytes) => Ok(bytes),
-        None => Err(TooShort),
+        None => Err(ParsingError::new(TooShort).at(pos)),
     }
 }
 
@@ -294,81 +1093,266 @@ fn read_f32(bytes: &[u8], i: &mut usize) -> ParsingResult<f32> {
     Ok(f32::from_be_bytes(bytes))
 }
 
-fn discover_section(bytes: &[u8], i: &mut usize, bytes_per_item: usize) -> ParsingResult<usize> {
-    let file_offset = *i;
-    *i += (read_u32(bytes, i)? as usize) * bytes_per_item;
-    Ok(file_offset)
+fn check_index(i: usize, count: usize, section: Section) -> ParsingResult<()> {
+    match i < count {
+        true => Ok(()),
+        false => Err(ParsingError::new(InvalidIndex).in_item(section, i)),
+    }
+}
+
+fn check_item(valid: bool, kind: ErrorKind, section: Section, i: usize, offset: usize) -> ParsingResult<()> {
+    match valid {
+        true => Ok(()),
+        false => Err(ParsingError::item(kind, section, i, offset)),
+    }
+}
+
+/// End of `length` items from `start`, which must not overflow
+fn checked_end(start: usize, length: usize, section: Section, i: usize, offset: usize) -> ParsingResult<usize> {
+    start.checked_add(length).ok_or(ParsingError::item(InvalidIndex, section, i, offset))
+}
+
+fn discover_section(bytes: &[u8], i: &mut usize, section: Section, bytes_per_item: usize) -> ParsingResult<Span> {
+    let in_section = |e: ParsingError| ParsingError { section: Some(section), ..e };
+    let count = read_u32(bytes, i).map_err(in_section)? as usize;
+    let offset = *i;
+    match count.checked_mul(bytes_per_item).and_then(|n| n.checked_add(offset)) {
+        Some(end) if end <= bytes.len() => {
+            *i = end;
+            Ok(Span { offset, count, stride: bytes_per_item })
+        },
+        _ => Err(in_section(ParsingError::new(TooShort).at(bytes.len()))),
+    }
+}
+
+/// Locates a section listed in the table of contents
+///
+/// Its items may be longer than `bytes_per_item` if they have fields
+/// from a later revision, which are then ignored.
+fn table_section(bytes: &[u8], section: Section, offset: usize, length: usize, bytes_per_item: usize) -> ParsingResult<Span> {
+    let in_section = |e: ParsingError| ParsingError { section: Some(section), ..e };
+    match offset.checked_add(length) {
+        Some(end) if end <= bytes.len() => (),
+        _ => return Err(in_section(ParsingError::new(TooShort).at(bytes.len()))),
+    }
+    if length < QUAD {
+        return Err(in_section(ParsingError::new(InvalidSectionLength).at(offset)));
+    }
+
+    let end = offset + length;
+
+    let mut i = offset;
+    let count = read_u32(bytes, &mut i).map_err(in_section)? as usize;
+    let stride = match count {
+        0 => bytes_per_item,
+        _ => (end - i) / count,
+    };
+
+    match stride >= bytes_per_item && i + count * stride == end {
+        true => Ok(Span { offset: i, count, stride }),
+        false => Err(in_section(ParsingError::new(InvalidSectionLength).at(offset))),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct SerializedProgram<T> {
     storage: T,
-    arguments: usize,
-    instructions: usize,
-    outputs: usize,
-    triangles: usize,
-    arcs: usize,
-    cubic_curves: usize,
-    quadratic_curves: usize,
-    lines: usize,
-    strokers: usize,
-    steps: usize,
-    paths: usize,
-    triangle_indexes: usize,
-    backgrounds: usize,
-    rendering_steps: usize,
-    string_bytes: usize,
+    revision: Option<u32>,
+    arguments: Span,
+    instructions: Span,
+    outputs: Span,
+    tracks: Span,
+    keyframes: Span,
+    playback: Span,
+    metadata: Span,
+    triangles: Span,
+    arcs: Span,
+    cubic_curves: Span,
+    quadratic_curves: Span,
+    lines: Span,
+    strokers: Span,
+    steps: Span,
+    paths: Span,
+    triangle_indexes: Span,
+    backgrounds: Span,
+    gradients: Span,
+    color_stops: Span,
+    colors: Span,
+    rendering_steps: Span,
+    string_bytes: Span,
 }
 
 const QUAD: usize = 4;
 
+/// Where the items of a section are in a file
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+struct Span {
+    /// byte offset of the first item
+    offset: usize,
+    count: usize,
+    /// bytes per item, which can exceed what this version reads
+    stride: usize,
+}
+
+impl Span {
+    fn item(&self, i: usize) -> usize {
+        self.offset + i * self.stride
+    }
+
+    /// Like [`Self::item`], for indexes read from the file
+    fn checked_item(&self, i: usize) -> Option<usize> {
+        i.checked_mul(self.stride)?.checked_add(self.offset)
+    }
+}
+
+/// Sections listed in the table of contents, with the size of their items;
+/// the id of a section is its index in this table
+const SECTIONS: [(Section, usize); 22] = [
+    (Section::Arguments, 7 * QUAD),
+    (Section::Instructions, 4 * QUAD),
+    (Section::Outputs, 2 * QUAD),
+    (Section::Tracks, 3 * QUAD),
+    (Section::Keyframes, 4 * QUAD),
+    (Section::Playback, 3 * QUAD),
+    (Section::Metadata, 2 * QUAD),
+    (Section::Triangles, 9 * QUAD),
+    (Section::Arcs, 3 * QUAD),
+    (Section::CubicCurves, 4 * QUAD),
+    (Section::QuadraticCurves, 3 * QUAD),
+    (Section::Lines, 2 * QUAD),
+    (Section::Strokers, 8 * QUAD),
+    (Section::Steps, 2 * QUAD),
+    (Section::Paths, 2 * QUAD),
+    (Section::TriangleIndexes, QUAD),
+    (Section::Backgrounds, 2 * QUAD),
+    (Section::Gradients, 6 * QUAD),
+    (Section::ColorStops, 3 * QUAD),
+    (Section::Colors, 2 * QUAD),
+    (Section::RenderingSteps, 5 * QUAD),
+    (Section::Strings, 1),
+];
+
+/// Sections of legacy (RWY0) files, in file order, with the size of their items
+const LEGACY_SECTIONS: [(Section, usize); 15] = [
+    (Section::Arguments, 7 * QUAD),
+    (Section::Instructions, 4 * QUAD),
+    (Section::Outputs, 2 * QUAD),
+    (Section::Triangles, 9 * QUAD),
+    (Section::Arcs, 3 * QUAD),
+    (Section::CubicCurves, 4 * QUAD),
+    (Section::QuadraticCurves, 3 * QUAD),
+    (Section::Lines, 2 * QUAD),
+    (Section::Strokers, 4 * QUAD),
+    (Section::Steps, 2 * QUAD),
+    (Section::Paths, 2 * QUAD),
+    (Section::TriangleIndexes, QUAD),
+    (Section::Backgrounds, 2 * QUAD),
+    (Section::RenderingSteps, 3 * QUAD),
+    (Section::Strings, 1),
+];
+
+fn section_id(section: Section) -> usize {
+    SECTIONS.iter().position(|(s, _)| *s == section).unwrap()
+}
+
 /// High Level API
 impl<T: AsRef<[u8]>> SerializedProgram<T> {
+    /// Parses the header and locates sections; items are checked by [`Self::validate`]
+    ///
+    /// Both the current format and the legacy (RWY0) one are accepted.
     pub fn new(storage: T) -> ParsingResult<Self> {
         let bytes = storage.as_ref();
-        bytes.strip_prefix(&MAGIC_BYTES).ok_or(NotARailwayFile)?;
-
+        let mut spans = [Span::default(); SECTIONS.len()];
         let mut i = MAGIC_BYTES.len();
         let i = &mut i;
 
-        let arguments = discover_section(bytes, i, 7 * QUAD)?;
-        let instructions = discover_section(bytes, i, 4 * QUAD)?;
-        let outputs = discover_section(bytes, i, 2 * QUAD)?;
-        let triangles = discover_section(bytes, i, 9 * QUAD)?;
-        let arcs = discover_section(bytes, i, 3 * QUAD)?;
-        let cubic_curves = discover_section(bytes, i, 4 * QUAD)?;
-        let quadratic_curves = discover_section(bytes, i, 3 * QUAD)?;
-        let lines = discover_section(bytes, i, 2 * QUAD)?;
-        let strokers = discover_section(bytes, i, 4 * QUAD)?;
-        let steps = discover_section(bytes, i, 2 * QUAD)?;
-        let paths = discover_section(bytes, i, 2 * QUAD)?;
-        let triangle_indexes = discover_section(bytes, i, 1 * QUAD)?;
-        let backgrounds = discover_section(bytes, i, 2 * QUAD)?;
-        let rendering_steps = discover_section(bytes, i, 3 * QUAD)?;
-        let string_bytes = discover_section(bytes, i, 1)?;
-
-        if *i == bytes.len() {
-            Ok(Self {
-                storage,
-                arguments,
-                instructions,
-                outputs,
-                triangles,
-                arcs,
-                cubic_curves,
-                quadratic_curves,
-                lines,
-                strokers,
-                steps,
-                paths,
-                triangle_indexes,
-                backgrounds,
-                rendering_steps,
-                string_bytes,
-            })
+        let revision = if bytes.starts_with(&LEGACY_MAGIC_BYTES) {
+            for (section, bytes_per_item) in LEGACY_SECTIONS {
+                spans[section_id(section)] = discover_section(bytes, i, section, bytes_per_item)?;
+            }
+
+            if *i != bytes.len() {
+                return Err(ParsingError::new(ExcessBytes).at(*i));
+            }
+
+            None
+        } else if bytes.starts_with(&MAGIC_BYTES) {
+            let in_header = |e: ParsingError| ParsingError { section: Some(Section::Header), ..e };
+            let revision = read_u32(bytes, i).map_err(in_header)?;
+            let entries = read_u32(bytes, i).map_err(in_header)?;
+
+            for _ in 0..entries {
+                let id = read_u32(bytes, i).map_err(in_header)? as usize;
+                let offset = read_u32(bytes, i).map_err(in_header)? as usize;
+                let length = read_u32(bytes, i).map_err(in_header)? as usize;
+
+                // sections from later revisions are skipped
+                if let Some((section, bytes_per_item)) = SECTIONS.get(id) {
+                    spans[id] = table_section(bytes, *section, offset, length, *bytes_per_item)?;
+                }
+            }
+
+            Some(revision)
         } else {
-            Err(ExcessBytes)
-        }
+            return Err(ParsingError::item(NotARailwayFile, Section::Header, 0, 0));
+        };
+
+        let [
+            arguments,
+            instructions,
+            outputs,
+            tracks,
+            keyframes,
+            playback,
+            metadata,
+            triangles,
+            arcs,
+            cubic_curves,
+            quadratic_curves,
+            lines,
+            strokers,
+            steps,
+            paths,
+            triangle_indexes,
+            backgrounds,
+            gradients,
+            color_stops,
+            colors,
+            rendering_steps,
+            string_bytes,
+        ] = spans;
+
+        Ok(Self {
+            storage,
+            revision,
+            arguments,
+            instructions,
+            outputs,
+            tracks,
+            keyframes,
+            playback,
+            metadata,
+            triangles,
+            arcs,
+            cubic_curves,
+            quadratic_curves,
+            lines,
+            strokers,
+            steps,
+            paths,
+            triangle_indexes,
+            backgrounds,
+            gradients,
+            color_stops,
+            colors,
+            rendering_steps,
+            string_bytes,
+        })
+    }
+
+    /// Format revision of the file, `None` for legacy (RWY0) files
+    pub fn revision(&self) -> Option<u32> {
+        self.revision
     }
 
     pub fn stack_size(&self) -> usize {
@@ -377,19 +1361,14 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
 
     pub fn compute(&self, stack: &mut [Couple], mut changes: Option<&mut [bool]>) -> ParsingResult<()> {
         let ins_count = self.instructions();
-        let mut current = self.arguments();
+        let arg_count = self.arguments();
 
         for i in 0..ins_count {
+            let current = arg_count + i;
             let instruction = self.instruction(i)?;
-            let get_op = |a| stack[..current].get(a).ok_or(InvalidOperation);
-
-            let operands = [
-                *get_op(instruction.operands[0])?,
-                *get_op(instruction.operands[1])?,
-                *get_op(instruction.operands[2])?,
-            ];
-
-            let result = compute(instruction, operands);
+            let offset = self.instructions.item(i);
+            let invalid = ParsingError::item(InvalidAddress, Section::Instructions, i, offset);
+            let result = compute(instruction, &stack[..current]).ok_or(invalid)?;
 
             if result != stack[current] {
                 if let Some(changes) = changes.as_mut() {
@@ -398,8 +1377,53 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
             }
 
             stack[current] = result;
+        }
 
-            current += 1;
+        Ok(())
+    }
+
+    /// Sets the [`TIME_ARGUMENT`] and the arguments which tracks drive
+    ///
+    /// Instructions must be computed again afterwards.
+    pub fn animate(&self, time: Float, stack: &mut [Couple], mut changes: Option<&mut [bool]>) -> ParsingResult<()> {
+        let mut set = |address: Address, value: Couple, section, i, offset| -> ParsingResult<()> {
+            let slot = stack.get_mut(address).ok_or(ParsingError::item(InvalidAddress, section, i, offset))?;
+            if *slot != value {
+                if let Some(changes) = changes.as_mut() {
+                    changes[address] = true;
+                }
+                *slot = value;
+            }
+            Ok(())
+        };
+
+        for i in 0..self.arguments() {
+            if self.argument(i)?.name == Some(TIME_ARGUMENT) {
+                set(i, Couple::new(time, 0.0), Section::Arguments, i, self.arguments.item(i))?;
+            }
+        }
+
+        for i in 0..self.tracks() {
+            let RawTrack { argument, keyframe_offset, stop_before } = self.raw_track(i)?;
+            let mut previous: Option<Keyframe> = None;
+            let mut value = None;
+
+            for k in keyframe_offset..stop_before {
+                let keyframe = self.keyframe(k)?;
+                if keyframe.time > time {
+                    value = Some(match previous {
+                        Some(previous) => previous.interpolate(&keyframe, time),
+                        None => keyframe.value,
+                    });
+                    break;
+                }
+                previous = Some(keyframe);
+            }
+
+            // after the last keyframe, its value stays
+            if let Some(value) = value.or(previous.map(|k| k.value)) {
+                set(argument, value, Section::Tracks, i, self.tracks.item(i))?;
+            }
         }
 
         Ok(())
@@ -416,27 +1440,26 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     fn read_nts<'a>(&'a self, i: &mut usize) -> ParsingResult<Option<&'a str>> {
         let str_offset = self.read_usize(i)?;
         if str_offset != (u32::MAX as usize) {
-            let bytes = self.storage.as_ref();
-            let str_start = self.string_bytes + QUAD + str_offset;
-            let mut len = 0;
-            while bytes[str_start + len] != 0 {
-                len += 1;
-            }
-            Ok(Some(from_utf8(&bytes[str_start..][..len]).map_err(|_| InvalidName)?))
+            let Span { offset, count, .. } = self.string_bytes;
+            let error = ParsingError::new(InvalidName).at(offset.saturating_add(str_offset));
+            let strings = &self.storage.as_ref()[offset..offset + count];
+            let string = strings.get(str_offset..).ok_or(error)?;
+            let len = string.iter().position(|b| *b == 0).ok_or(error)?;
+            Ok(Some(from_utf8(&string[..len]).map_err(|_| error)?))
         } else {
             Ok(None)
         }
     }
 
     pub fn arguments(&self) -> usize {
-        self.read_usize(&mut self.arguments.clone()).unwrap()
+        self.arguments.count
     }
 
-    pub fn argument<'a>(&'a self, i: usize) -> ParsingResult<Argument<&'a str>> {
-        self.arguments().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.arguments + QUAD + i * 7 * QUAD;
+    pub fn argument(&self, i: usize) -> ParsingResult<Argument<&str>> {
+        check_index(i, self.arguments(), Section::Arguments)?;
+        let mut b = self.arguments.item(i);
 
-        let name = self.read_nts(&mut b)?;
+        let name = self.read_nts(&mut b).map_err(|e| e.in_item(Section::Arguments, i))?;
         let x     = self.read_f32(&mut b)?;
         let y     = self.read_f32(&mut b)?;
         let min_x = self.read_f32(&mut b)?;
@@ -451,32 +1474,32 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn instructions(&self) -> usize {
-        self.read_usize(&mut self.instructions.clone()).unwrap()
+        self.instructions.count
     }
 
     pub fn instruction(&self, i: usize) -> ParsingResult<Instruction> {
-        self.instructions().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.instructions + QUAD + i * 4 * QUAD;
+        check_index(i, self.instructions(), Section::Instructions)?;
+        let mut b = self.instructions.item(i);
 
         let op = self.read_usize(&mut b)?;
         let a1 = self.read_usize(&mut b)?;
         let a2 = self.read_usize(&mut b)?;
         let a3 = self.read_usize(&mut b)?;
         Ok(Instruction {
-            operation: OPERATIONS[op],
+            operation: Operation::from_opcode(op as u32).ok_or(ParsingError::item(InvalidOperation, Section::Instructions, i, b - 4 * QUAD))?,
             operands: [a1, a2, a3],
         })
     }
 
     pub fn outputs(&self) -> usize {
-        self.read_usize(&mut self.outputs.clone()).unwrap()
+        self.outputs.count
     }
 
-    pub fn output<'a>(&'a self, i: usize) -> ParsingResult<Output<&'a str>> {
-        self.outputs().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.outputs + QUAD + i * 2 * QUAD;
+    pub fn output(&self, i: usize) -> ParsingResult<Output<&str>> {
+        check_index(i, self.outputs(), Section::Outputs)?;
+        let mut b = self.outputs.item(i);
 
-        let name = self.read_nts(&mut b)?;
+        let name = self.read_nts(&mut b).map_err(|e| e.in_item(Section::Outputs, i))?;
         let address = self.read_usize(&mut b)?;
         Ok(Output {
             name,
@@ -484,41 +1507,396 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
         })
     }
 
+    pub fn tracks(&self) -> usize {
+        self.tracks.count
+    }
+
+    pub fn raw_track(&self, i: usize) -> ParsingResult<RawTrack> {
+        check_index(i, self.tracks(), Section::Tracks)?;
+        let mut b = self.tracks.item(i);
+
+        let argument = self.read_usize(&mut b)?;
+        let keyframe_offset = self.read_usize(&mut b)?;
+        let stop_before = checked_end(keyframe_offset, self.read_usize(&mut b)?, Section::Tracks, i, self.tracks.item(i))?;
+        Ok(RawTrack {
+            argument,
+            keyframe_offset,
+            stop_before,
+        })
+    }
+
+    pub fn track(&self, i: usize) -> ParsingResult<Track> {
+        let RawTrack { argument, keyframe_offset, stop_before } = self.raw_track(i)?;
+        Ok(Track {
+            argument,
+            keyframes: (keyframe_offset..stop_before).map(|k| self.keyframe(k)).collect::<ParsingResult<_>>()?,
+        })
+    }
+
+    pub fn keyframes(&self) -> usize {
+        self.keyframes.count
+    }
+
+    pub fn keyframe(&self, i: usize) -> ParsingResult<Keyframe> {
+        check_index(i, self.keyframes(), Section::Keyframes)?;
+        let mut b = self.keyframes.item(i);
+
+        let time = self.read_f32(&mut b)?;
+        let x = self.read_f32(&mut b)?;
+        let y = self.read_f32(&mut b)?;
+        let interpolation = match self.read_usize(&mut b)? {
+            0 => Interpolation::Step,
+            1 => Interpolation::Linear,
+            2 => Interpolation::Smooth,
+            _ => return Err(ParsingError::item(InvalidInterpolation, Section::Keyframes, i, b - QUAD)),
+        };
+        Ok(Keyframe {
+            time,
+            value: Couple::new(x, y),
+            interpolation,
+        })
+    }
+
+    /// Duration, loop mode and frame rate of the animation, if specified
+    pub fn playback(&self) -> ParsingResult<Option<Playback>> {
+        if self.playback.count == 0 {
+            return Ok(None);
+        }
+
+        let mut b = self.playback.item(0);
+
+        let duration = self.read_f32(&mut b)?;
+        let loop_mode = match self.read_usize(&mut b)? {
+            0 => LoopMode::Once,
+            1 => LoopMode::Loop,
+            2 => LoopMode::PingPong,
+            _ => return Err(ParsingError::item(InvalidLoopMode, Section::Playback, 0, b - QUAD)),
+        };
+        let fps = self.read_f32(&mut b)?;
+        Ok(Some(Playback {
+            duration,
+            loop_mode,
+            fps,
+        }))
+    }
+
+    pub fn metadata_entries(&self) -> usize {
+        self.metadata.count
+    }
+
+    /// Key and value of a metadata entry
+    pub fn metadata_entry(&self, i: usize) -> ParsingResult<(&str, &str)> {
+        check_index(i, self.metadata_entries(), Section::Metadata)?;
+        let offset = self.metadata.item(i);
+        let mut b = offset;
+
+        let key = self.read_nts(&mut b).map_err(|e| e.in_item(Section::Metadata, i))?;
+        let value = self.read_nts(&mut b).map_err(|e| e.in_item(Section::Metadata, i))?;
+        match (key, value) {
+            (Some(key), Some(value)) => Ok((key, value)),
+            _ => Err(ParsingError::item(InvalidName, Section::Metadata, i, offset)),
+        }
+    }
+
+    /// Value of the first metadata entry with this key
+    pub fn metadata(&self, key: &str) -> ParsingResult<Option<&str>> {
+        for i in 0..self.metadata_entries() {
+            let (k, value) = self.metadata_entry(i)?;
+            if k == key {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Origin and size of the [`VIEW_BOX_KEY`] entry, if it's valid
+    pub fn view_box(&self) -> ParsingResult<Option<(Couple, Couple)>> {
+        let Some(view_box) = self.metadata(VIEW_BOX_KEY)? else {
+            return Ok(None);
+        };
+
+        let mut numbers = view_box.split_whitespace().map(|n| n.parse::<Float>().ok());
+        Ok(match [numbers.next(), numbers.next(), numbers.next(), numbers.next(), numbers.next()] {
+            [Some(Some(x)), Some(Some(y)), Some(Some(w)), Some(Some(h)), None] => Some((Couple::new(x, y), Couple::new(w, h))),
+            _ => None,
+        })
+    }
+
+    /// Size from the [`WIDTH_KEY`] and [`HEIGHT_KEY`] entries, if both are numbers
+    pub fn intrinsic_size(&self) -> ParsingResult<Option<Couple>> {
+        let number = |key| Ok::<_, ParsingError>(self.metadata(key)?.and_then(|n| n.trim().parse::<Float>().ok()));
+        Ok(match (number(WIDTH_KEY)?, number(HEIGHT_KEY)?) {
+            (Some(w), Some(h)) => Some(Couple::new(w, h)),
+            _ => None,
+        })
+    }
+
     pub fn rendering_steps(&self) -> usize {
-        self.read_usize(&mut self.rendering_steps.clone()).unwrap()
+        self.rendering_steps.count
     }
 
     pub fn raw_rendering_step(&self, i: usize) -> ParsingResult<RawRenderingStep> {
-        self.rendering_steps().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.rendering_steps + QUAD + i * 3 * QUAD;
+        check_index(i, self.rendering_steps(), Section::RenderingSteps)?;
+        let mut b = self.rendering_steps.item(i);
 
-        let clip_or_stroke = self.read_usize(&mut b)?;
+        let kind = self.read_usize(&mut b)?;
         let path_index = self.read_usize(&mut b)?;
         let arg_index = self.read_usize(&mut b)?;
-        Ok(match clip_or_stroke {
-            0 => RawRenderingStep::Clip(path_index, arg_index),
-            1 => RawRenderingStep::Stroke(path_index, arg_index),
-            _ => unreachable!(),
+        let (fill_rule, blend_mode) = if self.rendering_steps.stride < 5 * QUAD {
+            // legacy rendering steps have neither fill rules nor blend modes
+            (FillRule::NonZero, BlendMode::SourceOver)
+        } else {
+            let fill_rule = match self.read_usize(&mut b)? {
+                0 => FillRule::NonZero,
+                1 => FillRule::EvenOdd,
+                _ => return Err(ParsingError::item(InvalidFillRule, Section::RenderingSteps, i, b - QUAD)),
+            };
+            let blend_mode = self.read_usize(&mut b)?;
+            let blend_mode = BlendMode::from_index(blend_mode as u32)
+                .ok_or(ParsingError::item(InvalidBlendMode, Section::RenderingSteps, i, b - QUAD))?;
+            (fill_rule, blend_mode)
+        };
+        Ok(match kind {
+            0 => RawRenderingStep::Clip(path_index, arg_index, fill_rule, blend_mode),
+            1 => RawRenderingStep::Stroke(path_index, arg_index, blend_mode),
+            2 => RawRenderingStep::Gradient(path_index, arg_index, fill_rule, blend_mode),
+            3 => RawRenderingStep::Solid(path_index, arg_index, fill_rule, blend_mode),
+            4 => RawRenderingStep::Group(path_index, arg_index, blend_mode),
+            5 => RawRenderingStep::PushClip(path_index, fill_rule),
+            6 => RawRenderingStep::PopClip,
+            _ => return Err(ParsingError::item(InvalidRenderingStep, Section::RenderingSteps, i, self.rendering_steps.item(i))),
         })
     }
 
     pub fn rendering_step<'a>(&'a self, i: usize) -> ParsingResult<RenderingStep<PathIterator<'a, T>, BackgroundIterator<'a, T>>> {
         Ok(match self.raw_rendering_step(i)? {
-            RawRenderingStep::Clip(p, i) => RenderingStep::Clip(self.path(p)?, self.background(i)?),
-            RawRenderingStep::Stroke(p, i) => RenderingStep::Stroke(self.path(p)?, self.stroker(i)?),
+            RawRenderingStep::Clip(p, i, rule, mode) => RenderingStep::Clip(self.path(p)?, self.background(i)?, rule, mode),
+            RawRenderingStep::Stroke(p, i, mode) => RenderingStep::Stroke(self.path(p)?, self.stroker(i)?, mode),
+            RawRenderingStep::Gradient(p, i, rule, mode) => RenderingStep::Gradient(self.path(p)?, self.gradient(i)?, rule, mode),
+            RawRenderingStep::Solid(p, i, rule, mode) => RenderingStep::Solid(self.path(p)?, self.color(i)?, rule, mode),
+            RawRenderingStep::Group(length, opacity, mode) => RenderingStep::Group(length, opacity, mode),
+            RawRenderingStep::PushClip(p, rule) => RenderingStep::PushClip(self.path(p)?, rule),
+            RawRenderingStep::PopClip => RenderingStep::PopClip,
         })
     }
+
+    /// Walks every section once, checking opcodes, stack addresses,
+    /// step types, cross-section indexes and strings.
+    ///
+    /// Once this returned `Ok(())`, no accessor of this program can fail,
+    /// and neither can [`Self::animate`] nor [`Self::compute`] on a stack
+    /// of [`Self::stack_size`] couples.
+    pub fn validate(&self) -> ParsingResult<()> {
+        use Section::*;
+
+        let stack_size = self.stack_size();
+        let addresses = |section, i, offset, addresses: &[Address]| {
+            let valid = addresses.iter().all(|a| *a < stack_size);
+            check_item(valid, InvalidAddress, section, i, offset)
+        };
+
+        let arguments = self.arguments();
+        for i in 0..arguments {
+            self.argument(i)?;
+        }
+
+        for i in 0..self.instructions() {
+            let offset = self.instructions.item(i);
+            let instruction = self.instruction(i)?;
+            let computed = instruction.operands.iter().all(|a| *a < arguments + i);
+            // the matrix of Affine2 spans three couples
+            let matrix = match instruction.operation {
+                Operation::Affine2 => instruction.operands[1].checked_add(2).is_some_and(|end| end < arguments + i),
+                _ => true,
+            };
+            check_item(computed && matrix, InvalidAddress, Instructions, i, offset)?;
+        }
+
+        for i in 0..self.outputs() {
+            let offset = self.outputs.item(i);
+            addresses(Outputs, i, offset, &[self.output(i)?.address])?;
+        }
+
+        for i in 0..self.keyframes() {
+            self.keyframe(i)?;
+        }
+
+        for i in 0..self.tracks() {
+            let offset = self.tracks.item(i);
+            let track = self.raw_track(i)?;
+            check_item(track.argument < arguments, InvalidAddress, Tracks, i, offset)?;
+            check_item(track.stop_before <= self.keyframes(), InvalidIndex, Tracks, i, offset)?;
+
+            let mut previous = Float::NEG_INFINITY;
+            for k in track.keyframe_offset..track.stop_before {
+                let time = self.keyframe(k)?.time;
+                check_item(time >= previous, UnsortedKeyframes, Tracks, i, offset)?;
+                previous = time;
+            }
+        }
+
+        // there's at most one playback record
+        check_item(self.playback.count <= 1, InvalidIndex, Playback, 1, self.playback.item(1))?;
+        self.playback()?;
+
+        for i in 0..self.metadata_entries() {
+            self.metadata_entry(i)?;
+        }
+
+        for i in 0..self.triangles() {
+            let offset = self.triangles.item(i);
+            let triangle = self.triangle(i)?;
+            addresses(Triangles, i, offset, &triangle.points)?;
+            addresses(Triangles, i, offset, triangle.colors.as_flattened())?;
+        }
+
+        for i in 0..self.arcs() {
+            let offset = self.arcs.item(i);
+            let arc = self.arc(i)?;
+            addresses(Arcs, i, offset, &[arc.start_point, arc.center, arc.deltas])?;
+        }
+
+        for i in 0..self.cubic_curves() {
+            let offset = self.cubic_curves.item(i);
+            addresses(CubicCurves, i, offset, &self.cubic_curve(i)?.points)?;
+        }
+
+        for i in 0..self.quadratic_curves() {
+            let offset = self.quadratic_curves.item(i);
+            addresses(QuadraticCurves, i, offset, &self.quadratic_curve(i)?.points)?;
+        }
+
+        for i in 0..self.lines() {
+            let offset = self.lines.item(i);
+            addresses(Lines, i, offset, &self.line(i)?.points)?;
+        }
+
+        for i in 0..self.strokers() {
+            let offset = self.strokers.item(i);
+            let stroker = self.stroker(i)?;
+            addresses(Strokers, i, offset, &[stroker.pattern, stroker.width])?;
+            addresses(Strokers, i, offset, &stroker.color)?;
+        }
+
+        for i in 0..self.steps() {
+            let offset = self.steps.item(i);
+            let mut b = offset;
+            let step_type = self.read_usize(&mut b)?;
+            let index = self.read_usize(&mut b)?;
+            let count = match step_type {
+                0 => self.arcs(),
+                1 => self.cubic_curves(),
+                2 => self.quadratic_curves(),
+                3 => self.lines(),
+                // new contours have no data
+                4 => 1,
+                _ => return Err(ParsingError::item(InvalidStepType, Steps, i, offset)),
+            };
+            check_item(index < count, InvalidIndex, Steps, i, offset)?;
+        }
+
+        for i in 0..self.paths() {
+            let offset = self.paths.item(i);
+            let mut b = offset;
+            let first_step = self.read_usize(&mut b)?;
+            let length = self.read_usize(&mut b)?;
+            let end = checked_end(first_step, length, Paths, i, offset)?;
+            check_item(end <= self.steps(), InvalidIndex, Paths, i, offset)?;
+        }
+
+        for i in 0..self.triangle_indexes() {
+            let offset = self.triangle_indexes.item(i);
+            let valid = self.triangle_index(i)? < self.triangles();
+            check_item(valid, InvalidIndex, TriangleIndexes, i, offset)?;
+        }
+
+        for i in 0..self.backgrounds() {
+            let offset = self.backgrounds.item(i);
+            let valid = self.raw_background(i)?.stop_before <= self.triangle_indexes();
+            check_item(valid, InvalidIndex, Backgrounds, i, offset)?;
+        }
+
+        for i in 0..self.gradients() {
+            let offset = self.gradients.item(i);
+            let gradient = self.raw_gradient(i)?;
+            addresses(Gradients, i, offset, &gradient.points)?;
+            check_item(gradient.stop_before <= self.color_stops(), InvalidIndex, Gradients, i, offset)?;
+        }
+
+        for i in 0..self.color_stops() {
+            let offset = self.color_stops.item(i);
+            let stop = self.color_stop(i)?;
+            addresses(ColorStops, i, offset, &[stop.offset, stop.color[0], stop.color[1]])?;
+        }
+
+        for i in 0..self.colors() {
+            let offset = self.colors.item(i);
+            addresses(Colors, i, offset, &self.color(i)?)?;
+        }
+
+        // groups must be nested in each other, and pop the clips they push
+        let mut groups: Vec<(usize, usize)> = Vec::new();
+        // rendering steps which pushed the current clips
+        let mut clips: Vec<usize> = Vec::new();
+        let close_groups = |groups: &mut Vec<(usize, usize)>, clips: &[usize], before| {
+            while let Some((_, depth)) = groups.last().copied().filter(|(end, _)| *end < before) {
+                if let Some(push) = clips.get(depth) {
+                    return Err(ParsingError::item(ClipCrossesGroup, RenderingSteps, *push, self.rendering_steps.item(*push)));
+                }
+                groups.pop();
+            }
+            Ok(())
+        };
+
+        for i in 0..self.rendering_steps() {
+            let offset = self.rendering_steps.item(i);
+            close_groups(&mut groups, &clips, i)?;
+
+            let valid = match self.raw_rendering_step(i)? {
+                RawRenderingStep::Clip(p, b, _, _) => p < self.paths() && b < self.backgrounds(),
+                RawRenderingStep::Stroke(p, s, _) => p < self.paths() && s < self.strokers(),
+                RawRenderingStep::Gradient(p, g, _, _) => p < self.paths() && g < self.gradients(),
+                RawRenderingStep::Solid(p, c, _, _) => p < self.paths() && c < self.colors(),
+                RawRenderingStep::Group(length, opacity, _) => {
+                    addresses(RenderingSteps, i, offset, &[opacity])?;
+                    let end = checked_end(i, length, RenderingSteps, i, offset)?;
+                    let nested = groups.last().is_none_or(|(parent, _)| end <= *parent);
+                    groups.push((end, clips.len()));
+                    nested && end < self.rendering_steps()
+                },
+                RawRenderingStep::PushClip(p, _) => {
+                    clips.push(i);
+                    p < self.paths()
+                },
+                RawRenderingStep::PopClip => {
+                    check_item(!clips.is_empty(), UnbalancedClip, RenderingSteps, i, offset)?;
+                    let outer = groups.last().map_or(0, |(_, depth)| *depth);
+                    check_item(clips.len() > outer, ClipCrossesGroup, RenderingSteps, i, offset)?;
+                    clips.pop();
+                    true
+                },
+            };
+            check_item(valid, InvalidIndex, RenderingSteps, i, offset)?;
+        }
+
+        close_groups(&mut groups, &clips, usize::MAX)?;
+        if let Some(push) = clips.first() {
+            return Err(ParsingError::item(UnbalancedClip, RenderingSteps, *push, self.rendering_steps.item(*push)));
+        }
+
+        Ok(())
+    }
 }
 
 /// Low Level API
 impl<T: AsRef<[u8]>> SerializedProgram<T> {
     pub fn triangles(&self) -> usize {
-        self.read_usize(&mut self.triangles.clone()).unwrap()
+        self.triangles.count
     }
 
     pub fn triangle(&self, i: usize) -> ParsingResult<Triangle> {
-        self.triangles().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.triangles + QUAD + i * 9 * QUAD;
+        check_index(i, self.triangles(), Section::Triangles)?;
+        let mut b = self.triangles.item(i);
 
         let p0 = self.read_usize(&mut b)?;
         let p1 = self.read_usize(&mut b)?;
@@ -536,12 +1914,12 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn cubic_curves(&self) -> usize {
-        self.read_usize(&mut self.cubic_curves.clone()).unwrap()
+        self.cubic_curves.count
     }
 
     pub fn cubic_curve(&self, i: usize) -> ParsingResult<CubicCurve> {
-        self.cubic_curves().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.cubic_curves + QUAD + i * 4 * QUAD;
+        check_index(i, self.cubic_curves(), Section::CubicCurves)?;
+        let mut b = self.cubic_curves.item(i);
 
         let p0 = self.read_usize(&mut b)?;
         let p1 = self.read_usize(&mut b)?;
@@ -553,12 +1931,12 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn arcs(&self) -> usize {
-        self.read_usize(&mut self.arcs.clone()).unwrap()
+        self.arcs.count
     }
 
     pub fn arc(&self, i: usize) -> ParsingResult<Arc> {
-        self.arcs().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.arcs + QUAD + i * 3 * QUAD;
+        check_index(i, self.arcs(), Section::Arcs)?;
+        let mut b = self.arcs.item(i);
 
         let start_point = self.read_usize(&mut b)?;
         let center = self.read_usize(&mut b)?;
@@ -571,12 +1949,12 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn quadratic_curves(&self) -> usize {
-        self.read_usize(&mut self.quadratic_curves.clone()).unwrap()
+        self.quadratic_curves.count
     }
 
     pub fn quadratic_curve(&self, i: usize) -> ParsingResult<QuadraticCurve> {
-        self.quadratic_curves().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.quadratic_curves + QUAD + i * 3 * QUAD;
+        check_index(i, self.quadratic_curves(), Section::QuadraticCurves)?;
+        let mut b = self.quadratic_curves.item(i);
 
         let p0 = self.read_usize(&mut b)?;
         let p1 = self.read_usize(&mut b)?;
@@ -587,12 +1965,12 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn lines(&self) -> usize {
-        self.read_usize(&mut self.lines.clone()).unwrap()
+        self.lines.count
     }
 
     pub fn line(&self, i: usize) -> ParsingResult<Line> {
-        self.lines().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.lines + QUAD + i * 2 * QUAD;
+        check_index(i, self.lines(), Section::Lines)?;
+        let mut b = self.lines.item(i);
 
         let p0 = self.read_usize(&mut b)?;
         let p1 = self.read_usize(&mut b)?;
@@ -602,34 +1980,72 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn strokers(&self) -> usize {
-        self.read_usize(&mut self.strokers.clone()).unwrap()
+        self.strokers.count
     }
 
     pub fn stroker(&self, i: usize) -> ParsingResult<Stroker> {
-        self.strokers().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.strokers + QUAD + i * 4 * QUAD;
+        check_index(i, self.strokers(), Section::Strokers)?;
+        let mut b = self.strokers.item(i);
+        let invalid = |b| ParsingError::item(InvalidStroker, Section::Strokers, i, b - QUAD);
 
         let pattern = self.read_usize(&mut b)?;
         let width = self.read_usize(&mut b)?;
         let rg = self.read_usize(&mut b)?;
         let ba = self.read_usize(&mut b)?;
+        if self.strokers.stride < 8 * QUAD {
+            // legacy strokers have no style fields
+            return Ok(Stroker::new(pattern, width, [rg, ba]));
+        }
+
+        let closed = match self.read_usize(&mut b)? {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid(b)),
+        };
+        let cap = match self.read_usize(&mut b)? {
+            0 => LineCap::Butt,
+            1 => LineCap::Round,
+            2 => LineCap::Square,
+            _ => return Err(invalid(b)),
+        };
+        let join = match self.read_usize(&mut b)? {
+            0 => LineJoin::Miter,
+            1 => LineJoin::Round,
+            2 => LineJoin::Bevel,
+            _ => return Err(invalid(b)),
+        };
+        let miter_limit = self.read_f32(&mut b)?;
+        if miter_limit.is_nan() || miter_limit < 1.0 {
+            return Err(invalid(b));
+        }
         Ok(Stroker {
             pattern,
             width,
             color: [rg, ba],
+            closed,
+            cap,
+            join,
+            miter_limit,
         })
     }
 
+    pub fn steps(&self) -> usize {
+        self.steps.count
+    }
+
     pub fn paths(&self) -> usize {
-        self.read_usize(&mut self.paths.clone()).unwrap()
+        self.paths.count
     }
 
     pub fn raw_path(&self, i: usize) -> ParsingResult<RawPath> {
-        self.paths().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.paths + QUAD + i * 2 * QUAD;
-
-        let step_offset = self.steps + QUAD + self.read_usize(&mut b)? * 2 * QUAD;
-        let stop_before = step_offset + (self.read_usize(&mut b)? * 2 * QUAD);
+        check_index(i, self.paths(), Section::Paths)?;
+        let mut b = self.paths.item(i);
+
+        let first_step = self.read_usize(&mut b)?;
+        let length = self.read_usize(&mut b)?;
+        let invalid = ParsingError::item(InvalidIndex, Section::Paths, i, self.paths.item(i));
+        let step_offset = self.steps.checked_item(first_step).ok_or(invalid)?;
+        let stop_before = first_step.checked_add(length).and_then(|end| self.steps.checked_item(end)).ok_or(invalid)?;
         Ok(RawPath {
             step_offset,
             stop_before,
@@ -646,24 +2062,24 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
     }
 
     pub fn triangle_indexes(&self) -> usize {
-        self.read_usize(&mut self.triangle_indexes.clone()).unwrap()
+        self.triangle_indexes.count
     }
 
     pub fn triangle_index(&self, i: usize) -> ParsingResult<usize> {
-        self.triangle_indexes().checked_sub(i).ok_or(InvalidIndex)?;
-        self.read_usize(&mut (self.triangle_indexes + QUAD + i * 1 * QUAD).clone())
+        check_index(i, self.triangle_indexes(), Section::TriangleIndexes)?;
+        self.read_usize(&mut self.triangle_indexes.item(i))
     }
 
     pub fn backgrounds(&self) -> usize {
-        self.read_usize(&mut self.backgrounds.clone()).unwrap()
+        self.backgrounds.count
     }
 
     pub fn raw_background(&self, i: usize) -> ParsingResult<RawBackground> {
-        self.backgrounds().checked_sub(i).ok_or(InvalidIndex)?;
-        let mut b = self.backgrounds + QUAD + i * 2 * QUAD;
+        check_index(i, self.backgrounds(), Section::Backgrounds)?;
+        let mut b = self.backgrounds.item(i);
 
         let triangle_index_offset = self.read_usize(&mut b)?;
-        let stop_before = triangle_index_offset + self.read_usize(&mut b)?;
+        let stop_before = checked_end(triangle_index_offset, self.read_usize(&mut b)?, Section::Backgrounds, i, self.backgrounds.item(i))?;
         Ok(RawBackground {
             triangle_index_offset,
             stop_before,
@@ -678,6 +2094,78 @@ impl<T: AsRef<[u8]>> SerializedProgram<T> {
             stop_before,
         })
     }
+
+    pub fn gradients(&self) -> usize {
+        self.gradients.count
+    }
+
+    pub fn raw_gradient(&self, i: usize) -> ParsingResult<RawGradient> {
+        check_index(i, self.gradients(), Section::Gradients)?;
+        let mut b = self.gradients.item(i);
+
+        let kind = match self.read_usize(&mut b)? {
+            0 => GradientKind::Linear,
+            1 => GradientKind::Radial,
+            2 => GradientKind::Conic,
+            _ => return Err(ParsingError::item(InvalidGradient, Section::Gradients, i, b - QUAD)),
+        };
+        let p0 = self.read_usize(&mut b)?;
+        let p1 = self.read_usize(&mut b)?;
+        let p2 = self.read_usize(&mut b)?;
+        let stop_offset = self.read_usize(&mut b)?;
+        let stop_before = checked_end(stop_offset, self.read_usize(&mut b)?, Section::Gradients, i, self.gradients.item(i))?;
+        Ok(RawGradient {
+            kind,
+            points: [p0, p1, p2],
+            stop_offset,
+            stop_before,
+        })
+    }
+
+    pub fn gradient(&self, i: usize) -> ParsingResult<Gradient> {
+        let RawGradient { kind, points, stop_offset, stop_before } = self.raw_gradient(i)?;
+        Ok(Gradient {
+            kind,
+            points,
+            stops: (stop_offset..stop_before).map(|s| self.color_stop(s)).collect::<ParsingResult<_>>()?,
+        })
+    }
+
+    pub fn color_stops(&self) -> usize {
+        self.color_stops.count
+    }
+
+    pub fn color_stop(&self, i: usize) -> ParsingResult<ColorStop> {
+        check_index(i, self.color_stops(), Section::ColorStops)?;
+        let mut b = self.color_stops.item(i);
+
+        let offset = self.read_usize(&mut b)?;
+        let rg = self.read_usize(&mut b)?;
+        let ba = self.read_usize(&mut b)?;
+        Ok(ColorStop {
+            offset,
+            color: [rg, ba],
+        })
+    }
+
+    pub fn colors(&self) -> usize {
+        self.colors.count
+    }
+
+    pub fn color(&self, i: usize) -> ParsingResult<ColorAddress> {
+        check_index(i, self.colors(), Section::Colors)?;
+        let mut b = self.colors.item(i);
+
+        let rg = self.read_usize(&mut b)?;
+        let ba = self.read_usize(&mut b)?;
+        Ok([rg, ba])
+    }
+}
+
+pub struct RawTrack {
+    pub argument: Address,
+    pub keyframe_offset: usize,
+    pub stop_before: usize,
 }
 
 pub struct RawPath {
@@ -690,6 +2178,13 @@ pub struct RawBackground {
     pub stop_before: usize,
 }
 
+pub struct RawGradient {
+    pub kind: GradientKind,
+    pub points: [Address; 3],
+    pub stop_offset: usize,
+    pub stop_before: usize,
+}
+
 pub struct PathIterator<'a, T> {
     program: &'a SerializedProgram<T>,
     step_offset: usize,
@@ -708,7 +2203,13 @@ impl<'a, T: AsRef<[u8]>> Iterator for PathIterator<'a, T> {
                     1 => self.program.cubic_curve(index).map(|_| ()),
                     2 => self.program.quadratic_curve(index).map(|_| ()),
                     3 => self.program.line(index).map(|_| ()),
-                    _ => Err(InvalidStepType),
+                    4 => Ok(()),
+                    _ => {
+                        let steps = self.program.steps;
+                        let offset = self.step_offset - 2 * QUAD;
+                        let i = (offset - steps.offset) / steps.stride;
+                        Err(ParsingError::item(InvalidStepType, Section::Steps, i, offset))
+                    },
                 };
 
                 if let Err(e) = result {
@@ -719,6 +2220,7 @@ impl<'a, T: AsRef<[u8]>> Iterator for PathIterator<'a, T> {
                         1 => Ok(PathStep::CubicCurve(self.program.cubic_curve(index).unwrap())),
                         2 => Ok(PathStep::QuadraticCurve(self.program.quadratic_curve(index).unwrap())),
                         3 => Ok(PathStep::Line(self.program.line(index).unwrap())),
+                        4 => Ok(PathStep::NewContour),
                         _ => unreachable!(),
                     })
                 }
@@ -762,18 +2264,20 @@ fn bytes(n: usize) -> [u8; 4] {
     (n as u32).to_be_bytes()
 }
 
+/// Encodes a program; [`Document::serialize`](crate::Document::serialize) does so for a whole document
 pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
     arguments: &[Argument<S>],
     instructions: &[Instruction],
     outputs: &[Output<S>],
+    tracks: &[Track],
+    playback: Option<&Playback>,
+    metadata: &[(S, S)],
     rendering_steps: &[RenderingStep<P, B>],
 ) -> Vec<u8> {
     let mut output = Vec::new();
 
     let mut write_fn = |slice: [u8; 4]| output.extend_from_slice(&slice);
 
-    write_fn(MAGIC_BYTES);
-
     let mut string_section = Vec::new();
 
     write_fn(bytes(arguments.len()));
@@ -817,9 +2321,44 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
         write_fn(bytes(i.address));
     }
 
+    let mut flat_tracks = Vec::new();
+    let mut keyframes = Vec::new();
+    for track in tracks {
+        let frames: Vec<_> = track.keyframes.iter().map(|k| [
+            k.time.to_bits() as usize,
+            k.value.x.to_bits() as usize,
+            k.value.y.to_bits() as usize,
+            k.interpolation as usize,
+        ]).collect();
+        let [first_keyframe, length] = find_or_push_slice(&mut keyframes, &frames);
+        flat_tracks.push([track.argument, first_keyframe, length]);
+    }
+
+    for_each(&mut write_fn, &flat_tracks);
+    for_each(&mut write_fn, &keyframes);
+
+    write_fn(bytes(playback.is_some() as usize));
+    if let Some(playback) = playback {
+        write_fn(playback.duration.to_be_bytes());
+        write_fn(bytes(playback.loop_mode as usize));
+        write_fn(playback.fps.to_be_bytes());
+    }
+
+    write_fn(bytes(metadata.len()));
+    for (key, value) in metadata {
+        for string in [key, value] {
+            write_fn(bytes(string_section.len()));
+            string_section.extend_from_slice(string.as_ref().as_bytes());
+            string_section.push(0);
+        }
+    }
+
     let mut triangles = Vec::new();
     let mut triangle_indexes = Vec::new();
     let mut backgrounds = Vec::new();
+    let mut gradients = Vec::new();
+    let mut color_stops = Vec::new();
+    let mut colors = Vec::new();
     let mut paths = Vec::new();
     let mut arcs = Vec::new();
     let mut cubic_curves = Vec::new();
@@ -838,6 +2377,9 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
     }
 
     fn find_or_push_slice<T: 'static + Eq + Clone>(vec: &mut Vec<T>, slice: &[T]) -> [usize; 2] {
+        if slice.is_empty() {
+            return [0, 0];
+        }
         [vec.windows(slice.len()).position(|s| s == slice).unwrap_or_else(|| {
             let index = vec.len();
             vec.extend_from_slice(slice);
@@ -846,7 +2388,7 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
     }
 
     for step in rendering_steps {
-        let (clip_or_stroke, path, arg_index) = if let RenderingStep::Clip(path, background) = step {
+        let (kind, path, arg_index, fill_rule, blend_mode) = if let RenderingStep::Clip(path, background, rule, mode) = step {
 
             let mut indexes = Vec::with_capacity(background.as_ref().len());
             for triangle in background.as_ref() {
@@ -864,11 +2406,45 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
                 indexes.push([triangle_index]);
             }
 
-            (0, path, find_or_push(&mut backgrounds, find_or_push_slice(&mut triangle_indexes, &indexes)))
+            (0, path, find_or_push(&mut backgrounds, find_or_push_slice(&mut triangle_indexes, &indexes)), *rule, *mode)
+
+        } else if let RenderingStep::Stroke(path, s, mode) = step {
+
+            (1, path, find_or_push(&mut strokers, [
+                s.pattern,
+                s.width,
+                s.color[0],
+                s.color[1],
+                s.closed as usize,
+                s.cap as usize,
+                s.join as usize,
+                s.miter_limit.to_bits() as usize,
+            ]), FillRule::NonZero, *mode)
+
+        } else if let RenderingStep::Gradient(path, g, rule, mode) = step {
+
+            let stops: Vec<_> = g.stops.iter().map(|s| [s.offset, s.color[0], s.color[1]]).collect();
+            let [first_stop, length] = find_or_push_slice(&mut color_stops, &stops);
+            let [p0, p1, p2] = g.points;
+            (2, path, find_or_push(&mut gradients, [g.kind as usize, p0, p1, p2, first_stop, length]), *rule, *mode)
+
+        } else if let RenderingStep::Solid(path, color, rule, mode) = step {
 
-        } else if let RenderingStep::Stroke(path, s) = step {
+            (3, path, find_or_push(&mut colors, *color), *rule, *mode)
 
-            (1, path, find_or_push(&mut strokers, [s.pattern, s.width, s.color[0], s.color[1]]))
+        } else if let RenderingStep::Group(length, opacity, mode) = step {
+
+            flat_rendering_steps.push([4, *length, *opacity, 0, *mode as usize]);
+            continue;
+
+        } else if let RenderingStep::PushClip(path, rule) = step {
+
+            (5, path, 0, *rule, BlendMode::SourceOver)
+
+        } else if let RenderingStep::PopClip = step {
+
+            flat_rendering_steps.push([6, 0, 0, 0, 0]);
+            continue;
 
         } else {
             unreachable!()
@@ -881,11 +2457,12 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
                 PathStep::CubicCurve(curve) => [1, find_or_push(&mut cubic_curves, curve.points)],
                 PathStep::QuadraticCurve(curve) => [2, find_or_push(&mut quadratic_curves, curve.points)],
                 PathStep::Line(line) => [3, find_or_push(&mut lines, line.points)],
+                PathStep::NewContour => [4, 0],
             });
         }
         let path_index = find_or_push(&mut paths, find_or_push_slice(&mut steps, &tmp_steps));
 
-        flat_rendering_steps.push([clip_or_stroke, path_index, arg_index]);
+        flat_rendering_steps.push([kind, path_index, arg_index, fill_rule as usize, blend_mode as usize]);
     }
 
     fn for_each<const N: usize, F: FnMut([u8; 4])>(write_fn: &mut F, array: &[[usize; N]]) {
@@ -907,10 +2484,334 @@ pub fn serialize<S: AsRef<str>, P: AsRef<[PathStep]>, B: AsRef<[Triangle]>>(
     for_each(&mut write_fn, &paths);
     for_each(&mut write_fn, &triangle_indexes);
     for_each(&mut write_fn, &backgrounds);
+    for_each(&mut write_fn, &gradients);
+    for_each(&mut write_fn, &color_stops);
+    for_each(&mut write_fn, &colors);
     for_each(&mut write_fn, &flat_rendering_steps);
 
     write_fn(bytes(string_section.len()));
     output.extend_from_slice(&string_section);
 
-    output
+    // sections are written in table order: walk them to build the table
+    let header_len = 3 * QUAD + SECTIONS.len() * 3 * QUAD;
+    let mut file = Vec::with_capacity(header_len + output.len());
+    file.extend_from_slice(&MAGIC_BYTES);
+    file.extend_from_slice(&FORMAT_REVISION.to_be_bytes());
+    file.extend_from_slice(&bytes(SECTIONS.len()));
+
+    let mut i = 0;
+    for (id, (section, bytes_per_item)) in SECTIONS.iter().enumerate() {
+        let Span { count, .. } = discover_section(&output, &mut i, *section, *bytes_per_item).unwrap();
+        let length = QUAD + count * bytes_per_item;
+        file.extend_from_slice(&bytes(id));
+        file.extend_from_slice(&bytes(header_len + i - length));
+        file.extend_from_slice(&bytes(length));
+    }
+
+    file.extend_from_slice(&output);
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editing::Document;
+    use crate::building::ProgramBuilder;
+    use alloc::{vec, string::String};
+
+    /// A program with an item in every section
+    fn fixture() -> Document {
+        let path = vec![
+            PathStep::Line(Line { points: [1, 3] }),
+            PathStep::Arc(Arc { start_point: 1, center: 3, deltas: 2 }),
+            PathStep::NewContour,
+            PathStep::CubicCurve(CubicCurve { points: [1, 2, 3, 0] }),
+            PathStep::QuadraticCurve(QuadraticCurve { points: [1, 2, 3] }),
+        ];
+        let triangle = Triangle { points: [0, 1, 3], colors: [[2, 2]; 3] };
+        let gradient = Gradient {
+            kind: GradientKind::Linear,
+            points: [1, 3, 1],
+            stops: vec![ColorStop { offset: 1, color: [2, 2] }],
+        };
+
+        Document {
+            arguments: vec![
+                Argument::named("size".into(), Couple::new(100.0, 100.0)),
+                Argument::unnamed(Couple::new(0.5, 0.5)),
+                Argument::unnamed(Couple::new(1.0, 1.0)),
+            ],
+            instructions: vec![Instruction::new(Operation::Multiply2, 0, 1, 0)],
+            outputs: vec![Output { name: Some("center".into()), address: 3 }],
+            tracks: vec![Track {
+                argument: 1,
+                keyframes: vec![
+                    Keyframe { time: 0.0, value: Couple::new(0.5, 0.5), interpolation: Interpolation::Linear },
+                    Keyframe { time: 1.0, value: Couple::new(1.0, 1.0), interpolation: Interpolation::Linear },
+                ],
+            }],
+            playback: Some(Playback::new(2.0)),
+            metadata: vec![(TITLE_KEY.into(), String::from("Fixture"))],
+            rendering_steps: vec![
+                RenderingStep::Clip(path.clone(), vec![triangle], FillRule::NonZero, BlendMode::SourceOver),
+                RenderingStep::Stroke(path.clone(), Stroker::new(1, 2, [2, 2]), BlendMode::SourceOver),
+                RenderingStep::Gradient(path.clone(), gradient, FillRule::EvenOdd, BlendMode::Screen),
+                RenderingStep::Solid(path.clone(), [2, 2], FillRule::NonZero, BlendMode::Multiply),
+                RenderingStep::Group(2, 1, BlendMode::SourceOver),
+                RenderingStep::PushClip(path, FillRule::EvenOdd),
+                RenderingStep::PopClip,
+            ],
+        }
+    }
+
+    fn patched(bytes: &[u8], offset: usize, value: u32) -> Vec<u8> {
+        let mut bytes = bytes.to_vec();
+        bytes[offset..][..QUAD].copy_from_slice(&value.to_be_bytes());
+        bytes
+    }
+
+    fn check(bytes: &[u8]) -> ParsingResult<()> {
+        SerializedProgram::new(bytes)?.validate()
+    }
+
+    #[test]
+    fn fixture_is_valid() {
+        let bytes = fixture().serialize();
+        assert_eq!(check(&bytes), Ok(()));
+    }
+
+    #[test]
+    fn affine_matrices_span_three_couples() {
+        let mut b = ProgramBuilder::new();
+        let point = b.arg("point", Couple::new(2.0, 3.0));
+        let x_axis = b.constant(Couple::new(0.0, 1.0));
+        let y_axis = b.constant(Couple::new(-1.0, 0.0));
+        let origin = b.constant(Couple::new(10.0, 20.0));
+        let matrix = b.matrix(x_axis, y_axis, origin);
+        let mapped = b.affine(point, matrix);
+        b.output("mapped", mapped);
+
+        let bytes = b.serialize();
+        let program = SerializedProgram::new(&bytes[..]).unwrap();
+        assert_eq!(program.validate(), Ok(()));
+
+        let mut stack: Vec<_> = (0..program.arguments()).map(|i| program.argument(i).unwrap().value).collect();
+        stack.resize(program.stack_size(), Couple::zero());
+        program.compute(&mut stack, None).unwrap();
+        assert_eq!(stack[b.address(mapped)], Couple::new(7.0, 22.0));
+    }
+
+    #[test]
+    fn validate_locates_errors() {
+        use Section::*;
+
+        let bytes = fixture().serialize();
+        let p = SerializedProgram::new(&bytes[..]).unwrap();
+        let steps = p.rendering_steps;
+        let toc_length = |section| 3 * QUAD + section_id(section) * 3 * QUAD + 2 * QUAD;
+        let located = |kind, section: Option<Section>, offset| ParsingError { kind, section, index: None, offset: Some(offset) };
+
+        let mut legacy = LEGACY_MAGIC_BYTES.to_vec();
+        legacy.resize(QUAD + LEGACY_SECTIONS.len() * QUAD + 1, 0);
+
+        let cases = [
+            (patched(&bytes, 0, 0), ParsingError::item(NotARailwayFile, Header, 0, 0)),
+            (bytes[..10].to_vec(), located(TooShort, Some(Header), 8)),
+            (legacy, located(ExcessBytes, None, QUAD + LEGACY_SECTIONS.len() * QUAD)),
+            (
+                patched(&bytes, toc_length(Arguments), (QUAD + 3 * 7 * QUAD + 1) as u32),
+                located(InvalidSectionLength, Some(Arguments), p.arguments.offset - QUAD),
+            ),
+            (
+                patched(&bytes, p.arguments.item(0), 9999),
+                ParsingError::item(InvalidName, Arguments, 0, p.string_bytes.offset + 9999),
+            ),
+            (patched(&bytes, p.instructions.item(0), 0xFFFF), ParsingError::item(InvalidOperation, Instructions, 0, p.instructions.item(0))),
+            (patched(&bytes, p.outputs.item(0) + QUAD, 99), ParsingError::item(InvalidAddress, Outputs, 0, p.outputs.item(0))),
+            (
+                patched(&bytes, p.keyframes.item(0) + 3 * QUAD, 9),
+                ParsingError::item(InvalidInterpolation, Keyframes, 0, p.keyframes.item(0) + 3 * QUAD),
+            ),
+            (
+                patched(&bytes, p.keyframes.item(1), (-1.0f32).to_bits()),
+                ParsingError::item(UnsortedKeyframes, Tracks, 0, p.tracks.item(0)),
+            ),
+            (
+                patched(&bytes, p.playback.item(0) + QUAD, 9),
+                ParsingError::item(InvalidLoopMode, Playback, 0, p.playback.item(0) + QUAD),
+            ),
+            (
+                patched(&bytes, p.strokers.item(0) + 4 * QUAD, 7),
+                ParsingError::item(InvalidStroker, Strokers, 0, p.strokers.item(0) + 4 * QUAD),
+            ),
+            (patched(&bytes, p.steps.item(0), 9), ParsingError::item(InvalidStepType, Steps, 0, p.steps.item(0))),
+            (patched(&bytes, p.paths.item(0) + QUAD, 99), ParsingError::item(InvalidIndex, Paths, 0, p.paths.item(0))),
+            (patched(&bytes, p.gradients.item(0), 9), ParsingError::item(InvalidGradient, Gradients, 0, p.gradients.item(0))),
+            (patched(&bytes, steps.item(0), 99), ParsingError::item(InvalidRenderingStep, RenderingSteps, 0, steps.item(0))),
+            (
+                patched(&bytes, steps.item(0) + 3 * QUAD, 5),
+                ParsingError::item(InvalidFillRule, RenderingSteps, 0, steps.item(0) + 3 * QUAD),
+            ),
+            (
+                patched(&bytes, steps.item(0) + 4 * QUAD, 99),
+                ParsingError::item(InvalidBlendMode, RenderingSteps, 0, steps.item(0) + 4 * QUAD),
+            ),
+            // the matrix of Affine2 would start at the last argument
+            (patched(&bytes, p.instructions.item(0), 0x30), ParsingError::item(InvalidAddress, Instructions, 0, p.instructions.item(0))),
+            // lengths which overflow the sums of 32-bit targets
+            (patched(&bytes, p.tracks.item(0) + 2 * QUAD, u32::MAX), ParsingError::item(InvalidIndex, Tracks, 0, p.tracks.item(0))),
+            (patched(&bytes, p.paths.item(0) + QUAD, u32::MAX), ParsingError::item(InvalidIndex, Paths, 0, p.paths.item(0))),
+            (patched(&bytes, p.backgrounds.item(0) + QUAD, u32::MAX), ParsingError::item(InvalidIndex, Backgrounds, 0, p.backgrounds.item(0))),
+            (patched(&bytes, p.gradients.item(0) + 5 * QUAD, u32::MAX), ParsingError::item(InvalidIndex, Gradients, 0, p.gradients.item(0))),
+            (patched(&bytes, steps.item(4) + QUAD, u32::MAX), ParsingError::item(InvalidIndex, RenderingSteps, 4, steps.item(4))),
+            // the clip push becomes a pop
+            (patched(&bytes, steps.item(5), 6), ParsingError::item(UnbalancedClip, RenderingSteps, 5, steps.item(5))),
+            // the solid fill becomes a clip push which is never popped
+            (patched(&bytes, steps.item(3), 5), ParsingError::item(UnbalancedClip, RenderingSteps, 3, steps.item(3))),
+            // the clip pop becomes a solid fill, so the group ends with a clip
+            (patched(&bytes, steps.item(6), 3), ParsingError::item(ClipCrossesGroup, RenderingSteps, 5, steps.item(5))),
+        ];
+
+        for (bytes, error) in cases {
+            assert_eq!(check(&bytes), Err(error));
+        }
+    }
+
+    /// Sections of a current file as `(id, bytes)`, in table order
+    fn sections(bytes: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        let quad = |i: usize| u32::from_be_bytes(bytes[i..][..QUAD].try_into().unwrap());
+        (0..quad(2 * QUAD) as usize)
+            .map(|e| 3 * QUAD + e * 3 * QUAD)
+            .map(|entry| {
+                let (offset, length) = (quad(entry + QUAD) as usize, quad(entry + 2 * QUAD) as usize);
+                (quad(entry), bytes[offset..][..length].to_vec())
+            })
+            .collect()
+    }
+
+    /// Builds a current file from its sections
+    fn file(sections: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = MAGIC_BYTES.to_vec();
+        bytes.extend_from_slice(&0u32.to_be_bytes());
+        bytes.extend_from_slice(&(sections.len() as u32).to_be_bytes());
+
+        let mut offset = bytes.len() + sections.len() * 3 * QUAD;
+        for (id, data) in sections {
+            for quad in [*id, offset as u32, data.len() as u32] {
+                bytes.extend_from_slice(&quad.to_be_bytes());
+            }
+            offset += data.len();
+        }
+
+        for (_, data) in sections {
+            bytes.extend_from_slice(data);
+        }
+        bytes
+    }
+
+    /// Rewrites every item of a section with `f`
+    fn map_items(data: &[u8], f: impl Fn(&[u8]) -> Vec<u8>) -> Vec<u8> {
+        let count = u32::from_be_bytes(data[..QUAD].try_into().unwrap()) as usize;
+        let mut mapped = data[..QUAD].to_vec();
+        if let Some(stride) = (data.len() - QUAD).checked_div(count) {
+            for item in data[QUAD..].chunks(stride) {
+                mapped.extend(f(item));
+            }
+        }
+        mapped
+    }
+
+    #[test]
+    fn legacy_files_get_default_styles() {
+        let path = vec![PathStep::Line(Line { points: [0, 1] })];
+        let triangle = Triangle { points: [0, 1, 2], colors: [[1, 1]; 3] };
+        let stroker = Stroker {
+            closed: false,
+            cap: LineCap::Square,
+            join: LineJoin::Miter,
+            miter_limit: 10.0,
+            ..Stroker::new(1, 1, [1, 1])
+        };
+
+        let mut document = Document {
+            arguments: vec![Argument::named("size".into(), Couple::new(100.0, 100.0)), Argument::unnamed(Couple::new(1.0, 1.0))],
+            instructions: vec![Instruction::new(Operation::Multiply2, 0, 1, 0)],
+            outputs: vec![Output { name: Some("center".into()), address: 2 }],
+            tracks: vec![],
+            playback: None,
+            metadata: vec![],
+            rendering_steps: vec![
+                RenderingStep::Clip(path.clone(), vec![triangle], FillRule::EvenOdd, BlendMode::Screen),
+                RenderingStep::Stroke(path.clone(), stroker, BlendMode::Multiply),
+            ],
+        };
+
+        // RWY0 has no table of contents and shorter strokers and rendering steps
+        let current = sections(&document.serialize());
+        let mut legacy = LEGACY_MAGIC_BYTES.to_vec();
+        for (section, bytes_per_item) in LEGACY_SECTIONS {
+            let id = section_id(section) as u32;
+            let (_, data) = current.iter().find(|(i, _)| *i == id).unwrap();
+            legacy.extend(map_items(data, |item| item[..bytes_per_item].to_vec()));
+        }
+
+        let program = SerializedProgram::new(&legacy[..]).unwrap();
+        assert_eq!(program.revision(), None);
+        assert_eq!(program.validate(), Ok(()));
+
+        let stroker = program.stroker(0).unwrap();
+        assert!(stroker.closed);
+        assert_eq!((stroker.cap, stroker.join, stroker.miter_limit), (LineCap::Round, LineJoin::Round, 4.0));
+
+        document.rendering_steps = vec![
+            RenderingStep::Clip(path.clone(), vec![triangle], FillRule::NonZero, BlendMode::SourceOver),
+            RenderingStep::Stroke(path, Stroker::new(1, 1, [1, 1]), BlendMode::SourceOver),
+        ];
+        assert_eq!(Document::from_program(&program), Ok(document));
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped() {
+        let mut sections = sections(&fixture().serialize());
+        sections.insert(3, (SECTIONS.len() as u32, vec![0xAB; 9]));
+        let bytes = file(&sections);
+
+        let program = SerializedProgram::new(&bytes[..]).unwrap();
+        assert_eq!(program.revision(), Some(0));
+        assert_eq!(program.validate(), Ok(()));
+        assert_eq!(Document::from_program(&program), Ok(fixture()));
+    }
+
+    #[test]
+    fn missing_sections_are_empty() {
+        let metadata = section_id(Section::Metadata) as u32;
+        let mut sections = sections(&fixture().serialize());
+        sections.retain(|(id, _)| *id != metadata);
+        let bytes = file(&sections);
+
+        let program = SerializedProgram::new(&bytes[..]).unwrap();
+        assert_eq!(program.metadata_entries(), 0);
+        assert_eq!(program.validate(), Ok(()));
+
+        let mut expected = fixture();
+        expected.metadata.clear();
+        assert_eq!(Document::from_program(&program), Ok(expected));
+    }
+
+    #[test]
+    fn longer_items_are_read_up_to_known_fields() {
+        let widened = [Section::Strokers, Section::RenderingSteps].map(|s| section_id(s) as u32);
+        let mut sections = sections(&fixture().serialize());
+        for (id, data) in sections.iter_mut() {
+            if widened.contains(id) {
+                *data = map_items(data, |item| [item, &[0xAB; 2 * QUAD]].concat());
+            }
+        }
+        let bytes = file(&sections);
+
+        let program = SerializedProgram::new(&bytes[..]).unwrap();
+        assert_eq!(program.strokers.stride, 10 * QUAD);
+        assert_eq!(program.validate(), Ok(()));
+        assert_eq!(Document::from_program(&program), Ok(fixture()));
+    }
 }
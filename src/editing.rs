@@ -0,0 +1,201 @@
+//! Owned, editable railway documents
+
+use crate::computing::*;
+
+use alloc::{vec::Vec, string::String};
+
+/// A whole railway program, owned and editable
+///
+/// Unlike [`SerializedProgram`], paths and backgrounds are stored
+/// inline in each rendering step; [`Self::serialize`] deduplicates them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Document {
+    pub arguments: Vec<Argument<String>>,
+    pub instructions: Vec<Instruction>,
+    pub outputs: Vec<Output<String>>,
+    pub tracks: Vec<Track>,
+    pub playback: Option<Playback>,
+    /// Key/value pairs, such as [`TITLE_KEY`]
+    pub metadata: Vec<(String, String)>,
+    pub rendering_steps: Vec<RenderingStep<Vec<PathStep>, Vec<Triangle>>>,
+}
+
+impl Document {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(bytes: &[u8]) -> ParsingResult<Self> {
+        Self::from_program(&SerializedProgram::new(bytes)?)
+    }
+
+    /// Copies every item of a program; it is validated first.
+    pub fn from_program<T: AsRef<[u8]>>(program: &SerializedProgram<T>) -> ParsingResult<Self> {
+        program.validate()?;
+
+        let owned = |name: Option<&str>| name.map(String::from);
+        let mut document = Self::new();
+
+        for i in 0..program.arguments() {
+            let argument = program.argument(i)?;
+            document.arguments.push(Argument {
+                name: owned(argument.name),
+                value: argument.value,
+                range: argument.range,
+            });
+        }
+
+        for i in 0..program.instructions() {
+            document.instructions.push(program.instruction(i)?);
+        }
+
+        for i in 0..program.outputs() {
+            let output = program.output(i)?;
+            document.outputs.push(Output {
+                name: owned(output.name),
+                address: output.address,
+            });
+        }
+
+        for i in 0..program.tracks() {
+            document.tracks.push(program.track(i)?);
+        }
+
+        document.playback = program.playback()?;
+
+        for i in 0..program.metadata_entries() {
+            let (key, value) = program.metadata_entry(i)?;
+            document.metadata.push((key.into(), value.into()));
+        }
+
+        for i in 0..program.rendering_steps() {
+            document.rendering_steps.push(match program.rendering_step(i)? {
+                RenderingStep::Clip(path, background, rule, mode) => {
+                    RenderingStep::Clip(path.collect::<ParsingResult<_>>()?, background.collect::<ParsingResult<_>>()?, rule, mode)
+                },
+                RenderingStep::Stroke(path, stroker, mode) => {
+                    RenderingStep::Stroke(path.collect::<ParsingResult<_>>()?, stroker, mode)
+                },
+                RenderingStep::Gradient(path, gradient, rule, mode) => {
+                    RenderingStep::Gradient(path.collect::<ParsingResult<_>>()?, gradient, rule, mode)
+                },
+                RenderingStep::Solid(path, color, rule, mode) => {
+                    RenderingStep::Solid(path.collect::<ParsingResult<_>>()?, color, rule, mode)
+                },
+                RenderingStep::Group(length, opacity, mode) => RenderingStep::Group(length, opacity, mode),
+                RenderingStep::PushClip(path, rule) => RenderingStep::PushClip(path.collect::<ParsingResult<_>>()?, rule),
+                RenderingStep::PopClip => RenderingStep::PopClip,
+            });
+        }
+
+        Ok(document)
+    }
+
+    /// Encodes the document as a railway file
+    pub fn serialize(&self) -> Vec<u8> {
+        serialize(&self.arguments, &self.instructions, &self.outputs, &self.tracks, self.playback.as_ref(), &self.metadata, &self.rendering_steps)
+    }
+
+    pub fn stack_size(&self) -> usize {
+        self.arguments.len() + self.instructions.len()
+    }
+
+    /// Address of the argument with this name
+    pub fn argument(&self, name: &str) -> Option<Address> {
+        self.arguments.iter().position(|a| a.name.as_deref() == Some(name))
+    }
+
+    pub fn argument_mut(&mut self, name: &str) -> Option<&mut Argument<String>> {
+        self.arguments.iter_mut().find(|a| a.name.as_deref() == Some(name))
+    }
+
+    /// Address of the output with this name
+    pub fn output(&self, name: &str) -> Option<Address> {
+        let output = self.outputs.iter().find(|o| o.name.as_deref() == Some(name));
+        output.map(|o| o.address)
+    }
+
+    /// Appends an argument and returns its address.
+    ///
+    /// Instruction results move one slot up the stack;
+    /// every reference to them is updated.
+    pub fn push_argument(&mut self, argument: Argument<String>) -> Address {
+        let address = self.arguments.len();
+        self.for_each_address(|a| if *a >= address {
+            *a += 1;
+        });
+        self.arguments.push(argument);
+        address
+    }
+
+    /// Appends an instruction and returns the address of its result
+    pub fn push_instruction(&mut self, instruction: Instruction) -> Address {
+        let address = self.stack_size();
+        self.instructions.push(instruction);
+        address
+    }
+
+    fn for_each_address<F: FnMut(&mut Address)>(&mut self, mut f: F) {
+        for instruction in &mut self.instructions {
+            instruction.operands.iter_mut().for_each(&mut f);
+        }
+
+        for output in &mut self.outputs {
+            f(&mut output.address);
+        }
+
+        for track in &mut self.tracks {
+            f(&mut track.argument);
+        }
+
+        for step in &mut self.rendering_steps {
+            let path = match step {
+                RenderingStep::Clip(path, background, _, _) => {
+                    for triangle in background {
+                        triangle.points.iter_mut().for_each(&mut f);
+                        triangle.colors.as_flattened_mut().iter_mut().for_each(&mut f);
+                    }
+                    path
+                },
+                RenderingStep::Stroke(path, stroker, _) => {
+                    f(&mut stroker.pattern);
+                    f(&mut stroker.width);
+                    stroker.color.iter_mut().for_each(&mut f);
+                    path
+                },
+                RenderingStep::Gradient(path, gradient, _, _) => {
+                    gradient.points.iter_mut().for_each(&mut f);
+                    for stop in &mut gradient.stops {
+                        f(&mut stop.offset);
+                        stop.color.iter_mut().for_each(&mut f);
+                    }
+                    path
+                },
+                RenderingStep::Solid(path, color, _, _) => {
+                    color.iter_mut().for_each(&mut f);
+                    path
+                },
+                RenderingStep::Group(_, opacity, _) => {
+                    f(opacity);
+                    continue;
+                },
+                RenderingStep::PushClip(path, _) => path,
+                RenderingStep::PopClip => continue,
+            };
+
+            for step in path {
+                match step {
+                    PathStep::Arc(arc) => {
+                        f(&mut arc.start_point);
+                        f(&mut arc.center);
+                        f(&mut arc.deltas);
+                    },
+                    PathStep::CubicCurve(curve) => curve.points.iter_mut().for_each(&mut f),
+                    PathStep::QuadraticCurve(curve) => curve.points.iter_mut().for_each(&mut f),
+                    PathStep::Line(line) => line.points.iter_mut().for_each(&mut f),
+                    PathStep::NewContour => (),
+                }
+            }
+        }
+    }
+}
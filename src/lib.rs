@@ -1,8 +1,16 @@
 #![no_std]
 extern crate alloc;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 pub mod computing;
 pub mod rendering;
+pub mod assembling;
+pub mod editing;
+pub mod building;
+mod rasterizing;
+mod stroking;
 
 #[doc(inline)]
 pub use {
@@ -10,4 +18,5 @@ pub use {
     computing::PathStep,
     computing::serialize,
     rendering::NaiveRenderer,
+    editing::Document,
 };
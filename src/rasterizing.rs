@@ -0,0 +1,251 @@
+//! Rasterization of fills and strokes
+//!
+//! Shapes are drawn to a super-sampled bit mask: each pixel has one
+//! bit per sub-pixel, so overlapping pieces merge without seams before
+//! being averaged into an opacity mask. Pixels span as many 64-bit
+//! words as their sub-pixels need.
+//!
+//! Fills are scanned one sub-pixel row at a time; strokes are
+//! decomposed into convex pieces by the `stroking` module.
+
+use crate::computing::{Couple, Float, FillRule};
+
+use vek::vec::Vec2;
+
+#[allow(unused_imports)]
+use vek::num_traits::real::Real;
+
+use alloc::vec::Vec;
+
+/// Flattened contours of a path, each closed by repeating its first point
+#[derive(Debug, Clone, Default)]
+pub struct Contours {
+    pub points: Vec<Couple>,
+    /// index after the last point of each contour
+    ends: Vec<usize>,
+}
+
+impl Contours {
+    pub fn clear(&mut self) {
+        self.points.clear();
+        self.ends.clear();
+    }
+
+    /// Closes the contour made of the points pushed since the previous one
+    pub fn close(&mut self) {
+        let start = self.ends.last().copied().unwrap_or(0);
+        if let Some(first) = self.points.get(start).copied() {
+            self.points.push(first);
+            self.ends.push(self.points.len());
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &[Couple]> {
+        let starts = core::iter::once(0).chain(self.ends.iter().copied());
+        starts.zip(&self.ends).map(|(start, end)| &self.points[start..*end])
+    }
+}
+
+/// Reusable buffers for rasterization
+#[derive(Debug, Clone, Default)]
+pub struct Rasterizer {
+    /// one bit per sub-pixel, in `words` words per pixel
+    samples: Vec<u64>,
+    words: usize,
+    size: Vec2<usize>,
+    /// pixels which may have bits set: min x, min y, max x, max y
+    dirty: Option<[usize; 4]>,
+    /// deduplicated points of the polyline being stroked
+    pub(crate) points: Vec<Couple>,
+    /// x and winding direction of edges crossing a sub-pixel row
+    crossings: Vec<(Float, isize)>,
+}
+
+impl Rasterizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn resize<const SSAA: usize>(&mut self, size: Vec2<usize>) {
+        const { assert!(SSAA > 0, "SSAA must be at least 1") };
+        let words = (SSAA * SSAA).div_ceil(64);
+
+        if self.size != size || self.words != words {
+            self.size = size;
+            self.words = words;
+            self.samples.clear();
+            self.samples.resize(size.x * size.y * words, 0);
+            self.dirty = None;
+        }
+    }
+
+    /// Bits of the pixel at `i` (`y * width + x`)
+    fn pixel(&mut self, i: usize) -> &mut [u64] {
+        &mut self.samples[i * self.words..][..self.words]
+    }
+
+    /// Adds the inside of a path to the shape being drawn
+    pub fn fill<const SSAA: usize>(&mut self, contours: &Contours, rule: FillRule, size: Vec2<usize>) {
+        self.resize::<SSAA>(size);
+        let (w, h) = (size.x, size.y);
+        let path = &contours.points;
+        if path.is_empty() || w == 0 || h == 0 {
+            return;
+        }
+
+        let min = path.iter().fold(path[0], |min, p| Couple::partial_min(min, *p));
+        let max = path.iter().fold(path[0], |max, p| Couple::partial_max(max, *p));
+        let Some([_, min_y, _, max_y]) = self.mark_dirty(min, max) else { return };
+
+        let sub_pixel = (SSAA as Float).recip();
+        let columns = (w * SSAA) as Float;
+        let mut crossings = core::mem::take(&mut self.crossings);
+
+        for y in min_y..=max_y {
+            for sy in 0..SSAA {
+                let sample_y = (y as Float) + (sy as Float + 0.5) * sub_pixel;
+
+                crossings.clear();
+                for (s, e) in contours.iter().flat_map(|c| c.windows(2)).map(|s| (s[0], s[1])) {
+                    let direction = match (s.y <= sample_y, e.y <= sample_y) {
+                        (true, false) => 1,
+                        (false, true) => -1,
+                        _ => continue,
+                    };
+                    let x = s.x + (sample_y - s.y) * (e.x - s.x) / (e.y - s.y);
+                    crossings.push((x, direction));
+                }
+                crossings.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+                let mut winding = 0;
+                for i in 1..crossings.len() {
+                    winding += crossings[i - 1].1;
+                    let inside = match rule {
+                        FillRule::NonZero => winding != 0,
+                        FillRule::EvenOdd => winding % 2 != 0,
+                    };
+
+                    if inside {
+                        // sub-pixel columns whose center is in the span
+                        let column = |x: Float| (x * SSAA as Float - 0.5).ceil().clamp(0.0, columns) as usize;
+                        let (start, stop) = (column(crossings[i - 1].0), column(crossings[i].0));
+                        self.fill_span::<SSAA>(y, sy, start, stop);
+                    }
+                }
+            }
+        }
+
+        self.crossings = crossings;
+    }
+
+    /// Sets the bits of sub-pixel columns `start..stop` in a sub-pixel row
+    fn fill_span<const SSAA: usize>(&mut self, y: usize, sy: usize, start: usize, stop: usize) {
+        let first = sy * SSAA;
+        // whole rows of a pixel can be set at once if they're in one word
+        let row = match first % 64 + SSAA <= 64 {
+            true => Some((u64::MAX >> (64 - SSAA)) << (first % 64)),
+            false => None,
+        };
+
+        let mut column = start;
+        while column < stop {
+            let (x, sx) = (column / SSAA, column % SSAA);
+            let pixel = self.pixel(y * self.size.x + x);
+            match row {
+                Some(row) if sx == 0 && column + SSAA <= stop => {
+                    pixel[first / 64] |= row;
+                    column += SSAA;
+                },
+                _ => {
+                    let bit = first + sx;
+                    pixel[bit / 64] |= 1 << (bit % 64);
+                    column += 1;
+                },
+            }
+        }
+    }
+
+    /// Writes the coverage of everything added so far to `mask`
+    /// and starts a new shape.
+    pub fn finish<const SSAA: usize>(&mut self, mask: &mut [u8]) {
+        let Some([min_x, min_y, max_x, max_y]) = self.dirty.take() else { return };
+        let samples = SSAA * SSAA;
+
+        for y in min_y..=max_y {
+            let line = y * self.size.x;
+            for x in min_x..=max_x {
+                let bits = self.pixel(line + x);
+                let covered: usize = bits.iter().map(|word| word.count_ones() as usize).sum();
+                mask[line + x] = ((255 * covered) / samples) as u8;
+                bits.fill(0);
+            }
+        }
+    }
+
+    pub(crate) fn disc<const SSAA: usize>(&mut self, center: Couple, radius: Float) {
+        let r = Couple::new(radius, radius);
+        let radius_sq = radius * radius;
+        self.cover::<SSAA, _>(center - r, center + r, |p| p.distance_squared(center) <= radius_sq);
+    }
+
+    /// Draws a convex polygon, in any orientation
+    pub(crate) fn polygon<const SSAA: usize>(&mut self, points: &[Couple]) {
+        let min = points.iter().fold(points[0], |min, p| Couple::partial_min(min, *p));
+        let max = points.iter().fold(points[0], |max, p| Couple::partial_max(max, *p));
+
+        self.cover::<SSAA, _>(min, max, |p| {
+            let mut sides = [false; 2];
+            for i in 0..points.len() {
+                let (s, e) = (points[i], points[(i + 1) % points.len()]);
+                let cross = (e.x - s.x) * (p.y - s.y) - (e.y - s.y) * (p.x - s.x);
+                sides[0] |= cross < 0.0;
+                sides[1] |= cross > 0.0;
+            }
+            !(sides[0] && sides[1])
+        });
+    }
+
+    /// Extends the dirty rectangle to the pixels of a bounding box,
+    /// which are returned unless none is in the mask.
+    fn mark_dirty(&mut self, min: Couple, max: Couple) -> Option<[usize; 4]> {
+        let (w, h) = (self.size.x, self.size.y);
+        if w == 0 || h == 0 || !(min.x < w as Float && min.y < h as Float && max.x >= 0.0 && max.y >= 0.0) {
+            return None;
+        }
+
+        let min_x = min.x.max(0.0) as usize;
+        let min_y = min.y.max(0.0) as usize;
+        let max_x = (max.x as usize).min(w - 1);
+        let max_y = (max.y as usize).min(h - 1);
+
+        let dirty = self.dirty.get_or_insert([min_x, min_y, max_x, max_y]);
+        *dirty = [dirty[0].min(min_x), dirty[1].min(min_y), dirty[2].max(max_x), dirty[3].max(max_y)];
+
+        Some([min_x, min_y, max_x, max_y])
+    }
+
+    /// Sets the bits of sub-pixels which are in a shape
+    fn cover<const SSAA: usize, F: Fn(Couple) -> bool>(&mut self, min: Couple, max: Couple, inside: F) {
+        let Some([min_x, min_y, max_x, max_y]) = self.mark_dirty(min, max) else { return };
+        let w = self.size.x;
+
+        let sub_pixel = (SSAA as Float).recip();
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let bits = self.pixel(y * w + x);
+                for sy in 0..SSAA {
+                    for sx in 0..SSAA {
+                        let (word, bit) = ((sy * SSAA + sx) / 64, 1 << ((sy * SSAA + sx) % 64));
+                        if bits[word] & bit == 0 {
+                            let px = (x as Float) + (sx as Float + 0.5) * sub_pixel;
+                            let py = (y as Float) + (sy as Float + 0.5) * sub_pixel;
+                            if inside(Couple::new(px, py)) {
+                                bits[word] |= bit;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
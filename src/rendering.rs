@@ -3,14 +3,22 @@ use crate::computing::ParsingResult;
 use crate::computing::SerializedProgram;
 use crate::computing::PathStep;
 use crate::computing::RawBackground;
+use crate::computing::RawGradient;
+use crate::computing::GradientKind;
+use crate::computing::BlendMode;
 use crate::computing::RawRenderingStep::Clip;
 use crate::computing::RawRenderingStep::Stroke;
+use crate::computing::RawRenderingStep::Gradient;
+use crate::computing::RawRenderingStep::Solid;
+use crate::computing::RawRenderingStep::Group;
+use crate::computing::RawRenderingStep::PushClip;
+use crate::computing::RawRenderingStep::PopClip;
 use crate::computing::Float;
 use crate::computing::C_ZERO;
+use crate::rasterizing::{Rasterizer, Contours};
+use crate::stroking::StrokeStyle;
 
 use wizdraw::push_cubic_bezier_segments;
-use wizdraw::stroke;
-use wizdraw::fill;
 
 use vek::bezier::CubicBezier2;
 use vek::bezier::QuadraticBezier2;
@@ -21,7 +29,7 @@ use vek::num_traits::real::Real;
 
 use rgb::{RGBA, RGBA8, ComponentMap};
 
-use core::f32::consts::FRAC_PI_2;
+use core::f32::consts::{FRAC_PI_2, TAU};
 use alloc::{vec, vec::Vec, boxed::Box};
 
 #[derive(Debug, Clone, Copy)]
@@ -69,17 +77,121 @@ impl Triangle {
     }
 }
 
+/// Where a gradient's offsets come from, with cached values
+enum GradientShape {
+    Linear {
+        start: Couple,
+        /// start → end, divided by its squared length
+        step: Couple,
+    },
+    Radial {
+        focal: Couple,
+        /// focal → center
+        shift: Couple,
+        /// radius² - shift²
+        a: Float,
+    },
+    Conic {
+        center: Couple,
+        angle: Float,
+    },
+}
+
+impl GradientShape {
+    /// None if the gradient is degenerate
+    fn new(kind: GradientKind, p: [Couple; 3]) -> Option<Self> {
+        match kind {
+            GradientKind::Linear => {
+                let d = p[1] - p[0];
+                let len_sq = d.dot(d);
+                match len_sq > 0.0 {
+                    true => Some(Self::Linear { start: p[0], step: d / len_sq }),
+                    false => None,
+                }
+            },
+            GradientKind::Radial => {
+                let radius = p[1].x + p[1].y;
+                let mut shift = p[0] - p[2];
+                // keep the focal point inside of the circle
+                let max = radius * 0.99;
+                let distance = shift.magnitude();
+                if distance > max {
+                    shift *= max / distance;
+                }
+                match radius > 0.0 {
+                    true => Some(Self::Radial {
+                        focal: p[0] - shift,
+                        shift,
+                        a: radius * radius - shift.dot(shift),
+                    }),
+                    false => None,
+                }
+            },
+            GradientKind::Conic => Some(Self::Conic { center: p[0], angle: p[1].x }),
+        }
+    }
+
+    fn offset(&self, point: Couple) -> Float {
+        match *self {
+            Self::Linear { start, step } => (point - start).dot(step),
+            Self::Radial { focal, shift, a } => {
+                // offset of the circle going through this point, which
+                // moves from the focal point (0) to the outer circle (1)
+                let p = point - focal;
+                let b = p.dot(shift);
+                ((b * b + a * p.dot(p)).sqrt() - b) / a
+            },
+            Self::Conic { center, angle } => {
+                let p = point - center;
+                // same angles as Polar1
+                let turns = ((-p.y).atan2(p.x) - angle) / TAU;
+                turns - turns.floor()
+            },
+        }
+    }
+}
+
+/// Color at `offset` between color stops, padded with the first and last ones
+fn gradient_color(stops: &[(Float, RGBA<Float>)], offset: Float) -> RGBA8 {
+    let mut color = stops[0].1;
+    if offset > stops[0].0 {
+        color = stops[stops.len() - 1].1;
+        for pair in stops.windows(2) {
+            let ((o0, c0), (o1, c1)) = (pair[0], pair[1]);
+            if offset < o1 {
+                let w = (offset - o0) / (o1 - o0);
+                let lerp = |a: Float, b: Float| a + (b - a) * w;
+                color = RGBA::new(lerp(c0.r, c1.r), lerp(c0.g, c1.g), lerp(c0.b, c1.b), lerp(c0.a, c1.a));
+                break;
+            }
+        }
+    }
+    color.map(|float| float as u8)
+}
+
 pub struct NaiveRenderer<T> {
     program: SerializedProgram<T>,
     stack: Box<[Couple]>,
     stack_changes: Box<[bool]>,
-    flat_paths: Box<[Vec<Couple>]>,
+    flat_paths: Box<[Contours]>,
     triangles: Box<[Triangle]>,
     triangle_colors: Box<[([RGBA<Float>; 3], bool)]>,
+    rasterizer: Rasterizer,
+    dash: Vec<Couple>,
+    color_stops: Vec<(Float, RGBA<Float>)>,
+    /// offscreen layers of groups, one per depth
+    layers: Vec<Vec<RGBA8>>,
+    /// last step, opacity and blend mode of the groups being drawn
+    groups: Vec<(usize, Float, BlendMode)>,
+    /// coverage of the pushed clip paths, each one intersected with the previous
+    clips: Vec<Vec<u8>>,
+    clip_depth: usize,
 }
 
 impl<T: AsRef<[u8]>> NaiveRenderer<T> {
     pub fn new(program: SerializedProgram<T>) -> ParsingResult<Self> {
+        program.validate()?;
+
         let path_count = program.paths();
         let triangle_count = program.triangles();
         let stack_size = program.stack_size();
@@ -94,9 +206,16 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
             program,
             stack,
             stack_changes: vec![true; stack_size].into_boxed_slice(),
-            flat_paths: vec![Vec::new(); path_count].into_boxed_slice(),
+            flat_paths: vec![Contours::default(); path_count].into_boxed_slice(),
             triangles: vec![Triangle::invalid(); triangle_count].into_boxed_slice(),
             triangle_colors: vec![([RGBA::default(); 3], false); triangle_count].into_boxed_slice(),
+            rasterizer: Rasterizer::new(),
+            dash: Vec::new(),
+            color_stops: Vec::new(),
+            layers: Vec::new(),
+            groups: Vec::new(),
+            clips: Vec::new(),
+            clip_depth: 0,
         })
     }
 
@@ -181,10 +300,20 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
         })
     }
 
+    /// Sets the time argument and the arguments driven by tracks;
+    /// call [`Self::compute`] afterwards.
+    pub fn set_time(&mut self, seconds: Float) -> ParsingResult<()> {
+        self.program.animate(seconds, &mut self.stack, Some(&mut self.stack_changes))
+    }
+
     pub fn compute(&mut self) -> ParsingResult<()> {
         self.program.compute(&mut self.stack, Some(&mut self.stack_changes))
     }
 
+    /// Draws the computed program to `dst`
+    ///
+    /// Pixels are super-sampled `SSAA` times on each axis;
+    /// `SSAA_SQ` must be `SSAA * SSAA`.
     pub fn render<const SSAA: usize, const SSAA_SQ: usize>(
         &mut self,
         dst: &mut [RGBA8],
@@ -194,7 +323,12 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
         stride: usize,
         alpha_blend: bool,
     ) -> ParsingResult<()> {
+        assert_eq!(SSAA * SSAA, SSAA_SQ, "SSAA_SQ must be the square of SSAA");
         let mask_size = Vec2::new(w, h);
+        let base = match alpha_blend {
+            true => Compositing::Blend,
+            false => Compositing::Replace,
+        };
 
         // clear the rectangle
         if true {
@@ -238,6 +372,7 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                         let b = self.stack_changes[b];
                         was_updated |= a | b;
                     }
+                    PathStep::NewContour => (),
                 }
                 if was_updated {
                     break;
@@ -248,7 +383,7 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                 continue;
             }
 
-            let mut flat = &mut self.flat_paths[p];
+            let flat = &mut self.flat_paths[p];
             flat.clear();
             for step in self.program.path(p)? {
                 match step? {
@@ -284,7 +419,7 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                                 end,
                             };
 
-                            push_cubic_bezier_segments::<8>(&curve, 0.4, &mut flat);
+                            push_cubic_bezier_segments::<8>(&curve, 0.4, &mut flat.points);
 
                             end
                         };
@@ -310,7 +445,7 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                             ctrl1: self.stack[c],
                             end: self.stack[d],
                         };
-                        push_cubic_bezier_segments::<8>(&curve, 0.6, &mut flat);
+                        push_cubic_bezier_segments::<8>(&curve, 0.6, &mut flat.points);
                     }
                     PathStep::QuadraticCurve(curve) => {
                         let [a, b, c] = curve.points;
@@ -319,18 +454,18 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                             ctrl: self.stack[b],
                             end: self.stack[c],
                         };
-                        push_cubic_bezier_segments::<8>(&curve.into_cubic(), 0.6, &mut flat);
+                        push_cubic_bezier_segments::<8>(&curve.into_cubic(), 0.6, &mut flat.points);
                     }
                     PathStep::Line(line) => {
                         let [a, b] = line.points;
-                        flat.push(self.stack[a]);
-                        flat.push(self.stack[b]);
+                        flat.points.push(self.stack[a]);
+                        flat.points.push(self.stack[b]);
                     }
+                    PathStep::NewContour => flat.close(),
                 }
             }
-            if flat.first().is_some() {
-                flat.push(flat[0]);
-            }
+            // fills need closed paths; open strokes skip the last point
+            flat.close();
         }
 
         // update triangles
@@ -360,19 +495,66 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
 
         self.stack_changes.fill(false);
 
+        self.groups.clear();
+        self.clip_depth = 0;
+
         let rendering_step_count = self.program.rendering_steps();
         for r in 0..rendering_step_count {
+            self.end_groups(r, dst, mask_size, stride, base);
             let rendering_step = self.program.raw_rendering_step(r)?;
 
-            let path_index = match rendering_step {
-                Clip(i, _) => i,
-                Stroke(i, _) => i,
+            let (path_index, mode) = match rendering_step {
+                Clip(i, _, _, mode) => (i, mode),
+                Stroke(i, _, mode) => (i, mode),
+                Gradient(i, _, _, mode) => (i, mode),
+                Solid(i, _, _, mode) => (i, mode),
+                Group(length, opacity, mode) => {
+                    let depth = self.groups.len();
+                    if self.layers.len() == depth {
+                        self.layers.push(Vec::new());
+                    }
+
+                    let layer = &mut self.layers[depth];
+                    layer.clear();
+                    layer.resize(w * h, RGBA8::new(0, 0, 0, 0));
+                    self.groups.push((r + length, self.stack[opacity].x, mode));
+                    continue;
+                },
+                PushClip(i, rule) => {
+                    let depth = self.clip_depth;
+                    if self.clips.len() == depth {
+                        self.clips.push(Vec::new());
+                    }
+
+                    let (parents, clips) = self.clips.split_at_mut(depth);
+                    let clip = &mut clips[0];
+                    clip.clear();
+                    clip.resize(w * h, 0);
+                    self.rasterizer.fill::<SSAA>(&self.flat_paths[i], rule, mask_size);
+                    self.rasterizer.finish::<SSAA>(clip);
+                    intersect(clip, parents.last().map(|c| &c[..]));
+                    self.clip_depth += 1;
+                    continue;
+                },
+                PopClip => {
+                    self.clip_depth -= 1;
+                    continue;
+                },
             };
             let flat_path = &self.flat_paths[path_index];
+
+            // steps of groups are drawn in their layer
+            let (dst, stride, compositing) = match self.groups.len() {
+                0 => (&mut *dst, stride, base),
+                depth => (&mut self.layers[depth - 1][..], w, Compositing::Exact),
+            };
+            let clip = self.clip_depth.checked_sub(1).map(|depth| &self.clips[depth][..]);
             
             mask.fill(0);
-            if let Clip(_, i) = rendering_step {
-                fill::<SSAA, SSAA_SQ>(&flat_path, mask, mask_size);
+            if let Clip(_, i, rule, _) = rendering_step {
+                self.rasterizer.fill::<SSAA>(flat_path, rule, mask_size);
+                self.rasterizer.finish::<SSAA>(mask);
+                intersect(mask, clip);
 
                 let RawBackground {
                     triangle_index_offset: offset,
@@ -396,46 +578,320 @@ impl<T: AsRef<[u8]>> NaiveRenderer<T> {
                                         false => Triangle::color_at(weights, colors),
                                     };
 
-                                    blend_pixel(&mut dst[line + x], color, q, alpha_blend);
+                                    blend(&mut dst[line + x], color, q, mode, compositing);
                                 }
                             }
                         }
                     }
                     line += stride;
                 }
-            } else if let Stroke(_, i) = rendering_step {
-                let stroker = self.program.stroker(i)?;
+            } else if let Gradient(_, i, rule, _) = rendering_step {
+                self.rasterizer.fill::<SSAA>(flat_path, rule, mask_size);
+                self.rasterizer.finish::<SSAA>(mask);
+                intersect(mask, clip);
+
+                let RawGradient {
+                    kind,
+                    points,
+                    stop_offset,
+                    stop_before,
+                } = self.program.raw_gradient(i)?;
 
-                let p = self.stack[stroker.pattern];
-                let _p = [p.x, p.y];
-                let stroke_width = self.stack[stroker.width];
-                stroke::<SSAA>(&flat_path, mask, mask_size, stroke_width.x + stroke_width.y);
+                self.color_stops.clear();
+                for s in stop_offset..stop_before {
+                    let stop = self.program.color_stop(s)?;
+                    let offset = self.stack[stop.offset].x;
+                    self.color_stops.push((offset, color(self.stack[stop.color[0]], self.stack[stop.color[1]])));
+                }
 
-                let color = color(self.stack[stroker.color[0]], self.stack[stroker.color[1]]);
-                let color = color.map(|float| float as u8);
+                let shape = GradientShape::new(kind, points.map(|p| self.stack[p]));
+                let Some(shape) = shape.filter(|_| !self.color_stops.is_empty()) else {
+                    continue;
+                };
 
                 let mut mask = mask.iter();
                 let mut line = 0;
-                for _ in 0..h {
+                for y in 0..h {
                     for x in 0..w {
                         let q = *mask.next().unwrap();
                         if q != 0 {
-                            blend_pixel(&mut dst[line + x], color, q, alpha_blend);
+                            let offset = shape.offset(Couple::new(x as Float, y as Float));
+                            let color = gradient_color(&self.color_stops, offset);
+                            blend(&mut dst[line + x], color, q, mode, compositing);
                         }
                     }
                     line += stride;
                 }
+            } else if let Stroke(_, i, _) = rendering_step {
+                let stroker = self.program.stroker(i)?;
+                let pattern = self.stack[stroker.pattern];
+                let stroke_width = self.stack[stroker.width];
+                let style = StrokeStyle {
+                    width: stroke_width.x + stroke_width.y,
+                    cap: stroker.cap,
+                    join: stroker.join,
+                    miter_limit: stroker.miter_limit,
+                };
+
+                let rasterizer = &mut self.rasterizer;
+
+                // patterns shorter than a pixel would be invisible anyway
+                let dashed = pattern.x > 0.0 && pattern.y > 0.0 && (pattern.x + pattern.y) >= 1.0;
+
+                for contour in flat_path.iter() {
+                    let contour = match stroker.closed {
+                        true => contour,
+                        false => &contour[..contour.len() - 1],
+                    };
+
+                    // contours with too many dashes are stroked solid
+                    let length: Float = contour.windows(2).map(|s| s[0].distance(s[1])).sum();
+                    if dashed && length / (pattern.x + pattern.y) <= MAX_DASHES {
+                        split_dashes(contour, pattern.x, pattern.y, &mut self.dash, |dash| {
+                            rasterizer.stroke::<SSAA>(dash, false, style, mask_size);
+                        });
+                    } else {
+                        rasterizer.stroke::<SSAA>(contour, stroker.closed, style, mask_size);
+                    }
+                }
+
+                rasterizer.finish::<SSAA>(mask);
+                intersect(mask, clip);
+
+                let color = color(self.stack[stroker.color[0]], self.stack[stroker.color[1]]);
+                blend_mask(dst, mask, mask_size, stride, color.map(|float| float as u8), mode, compositing);
+            } else if let Solid(_, i, rule, _) = rendering_step {
+                self.rasterizer.fill::<SSAA>(flat_path, rule, mask_size);
+                self.rasterizer.finish::<SSAA>(mask);
+                intersect(mask, clip);
+
+                let [rg, ba] = self.program.color(i)?;
+                let color = color(self.stack[rg], self.stack[ba]);
+                blend_mask(dst, mask, mask_size, stride, color.map(|float| float as u8), mode, compositing);
             }
         }
 
+        self.end_groups(rendering_step_count, dst, mask_size, stride, base);
+
         Ok(())
     }
+
+    /// Composites the layers of groups which end before `step`
+    /// with their parent
+    fn end_groups(&mut self, step: usize, dst: &mut [RGBA8], size: Vec2<usize>, stride: usize, base: Compositing) {
+        while let Some(&(end, opacity, mode)) = self.groups.last() {
+            if end >= step {
+                break;
+            }
+
+            self.groups.pop();
+            let depth = self.groups.len();
+            let (parents, layers) = self.layers.split_at_mut(depth);
+            let (dst, stride, compositing) = match depth {
+                0 => (&mut *dst, stride, base),
+                _ => (&mut parents[depth - 1][..], size.x, Compositing::Exact),
+            };
+
+            let opacity = (opacity.clamp(0.0, 1.0) * 255.0) as u8;
+            if opacity == 0 {
+                continue;
+            }
+
+            let mut layer = layers[0].iter();
+            let mut line = 0;
+            for _ in 0..size.y {
+                for x in 0..size.x {
+                    let pixel = *layer.next().unwrap();
+                    if pixel.a != 0 {
+                        blend(&mut dst[line + x], pixel, opacity, mode, compositing);
+                    }
+                }
+                line += stride;
+            }
+        }
+    }
+}
+
+/// Dash count above which strokes are drawn solid
+const MAX_DASHES: Float = 4096.0;
+
+/// Cuts a polyline into dashes of `dash` length, separated by `gap`
+///
+/// The pattern starts at the first point and continues across corners.
+fn split_dashes<F: FnMut(&[Couple])>(path: &[Couple], dash: Float, gap: Float, buffer: &mut Vec<Couple>, mut f: F) {
+    let mut drawing = true;
+    let mut left = dash;
+
+    buffer.clear();
+    buffer.extend(path.first());
+
+    for segment in path.windows(2) {
+        let (mut start, end) = (segment[0], segment[1]);
+        let mut length = start.distance(end);
+
+        while length > left {
+            let point = start + (end - start) * (left / length);
+            length -= left;
+            start = point;
+            buffer.push(point);
+
+            // a dash ends here, or the next one starts here
+            if drawing {
+                f(buffer);
+                buffer.clear();
+            }
+
+            drawing = !drawing;
+            left = match drawing {
+                true => dash,
+                false => gap,
+            };
+        }
+
+        left -= length;
+        if drawing {
+            buffer.push(end);
+        }
+    }
+
+    if drawing && buffer.len() > 1 {
+        f(buffer);
+    }
+}
+
+/// Blends one color wherever the mask isn't empty
+fn blend_mask(dst: &mut [RGBA8], mask: &[u8], size: Vec2<usize>, stride: usize, color: RGBA8, mode: BlendMode, compositing: Compositing) {
+    let mut mask = mask.iter();
+    let mut line = 0;
+    for _ in 0..size.y {
+        for x in 0..size.x {
+            let q = *mask.next().unwrap();
+            if q != 0 {
+                blend(&mut dst[line + x], color, q, mode, compositing);
+            }
+        }
+        line += stride;
+    }
+}
+
+/// Restricts a mask to the current clip region
+fn intersect(mask: &mut [u8], clip: Option<&[u8]>) {
+    if let Some(clip) = clip {
+        for (q, c) in mask.iter_mut().zip(clip) {
+            *q = ((*q as u16 * *c as u16) / 255) as u8;
+        }
+    }
 }
 
 fn color(rg: Couple, ba: Couple) -> RGBA<f32> {
     RGBA::new(rg.x * 255.0, rg.y * 255.0, ba.x * 255.0, ba.y * 255.0)
 }
 
+type BlendFunction = fn(Float, Float) -> Float;
+
+fn source(_: Float, s: Float) -> Float { s }
+fn multiply(b: Float, s: Float) -> Float { b * s }
+fn screen(b: Float, s: Float) -> Float { b + s - b * s }
+fn darken(b: Float, s: Float) -> Float { b.min(s) }
+fn lighten(b: Float, s: Float) -> Float { b.max(s) }
+
+fn overlay(b: Float, s: Float) -> Float {
+    match b <= 0.5 {
+        true => multiply(s, 2.0 * b),
+        false => screen(s, 2.0 * b - 1.0),
+    }
+}
+
+/// Color blending of each mode, from the backdrop and source channels;
+/// Porter-Duff operators and additions keep the source color
+const BLEND_FUNCTIONS: [BlendFunction; 13] = [
+    source,
+    multiply,
+    screen,
+    overlay,
+    darken,
+    lighten,
+    source,
+    source,
+    source,
+    source,
+    source,
+    source,
+    source,
+];
+
+/// Porter-Duff factors of the source and the destination
+fn porter_duff(mode: BlendMode, src_alpha: Float, dst_alpha: Float) -> (Float, Float) {
+    use BlendMode::*;
+
+    match mode {
+        Add => (1.0, 1.0),
+        SourceIn => (dst_alpha, 0.0),
+        SourceOut => (1.0 - dst_alpha, 0.0),
+        SourceAtop => (dst_alpha, 1.0 - src_alpha),
+        DestinationIn => (0.0, src_alpha),
+        DestinationOut => (0.0, 1.0 - src_alpha),
+        Xor => (1.0 - dst_alpha, 1.0 - src_alpha),
+        // blended colors are drawn over the destination
+        _ => (1.0, 1.0 - src_alpha),
+    }
+}
+
+/// How rendering steps are drawn on their destination
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Compositing {
+    /// without `alpha_blend`, every mode behaves like source-over
+    /// and replaces the destination
+    Replace,
+    Blend,
+    /// layers of groups start transparent, so their alpha must be exact
+    Exact,
+}
+
+#[inline(always)]
+fn blend(dst_pixel: &mut RGBA8, src_pixel: RGBA8, mask_alpha: u8, mode: BlendMode, compositing: Compositing) {
+    match (mode, compositing) {
+        (_, Compositing::Replace) => blend_pixel(dst_pixel, src_pixel, mask_alpha, false),
+        (BlendMode::SourceOver, Compositing::Blend) => blend_pixel(dst_pixel, src_pixel, mask_alpha, true),
+        _ => composite_pixel(dst_pixel, src_pixel, mask_alpha, mode),
+    }
+}
+
+/// Composites a pixel with any blend mode, as in the W3C
+/// compositing specification; partially covered pixels are faded
+/// between the destination and the result.
+pub fn composite_pixel(dst_pixel: &mut RGBA8, src_pixel: RGBA8, mask_alpha: u8, mode: BlendMode) {
+    let unit = |c: u8| c as Float / 255.0;
+    let blend = BLEND_FUNCTIONS[mode as usize];
+
+    let coverage = unit(mask_alpha);
+    let src_alpha = unit(src_pixel.a);
+    let dst_alpha = unit(dst_pixel.a);
+    let (src_factor, dst_factor) = porter_duff(mode, src_alpha, dst_alpha);
+
+    let composited_alpha = (src_alpha * src_factor + dst_alpha * dst_factor).min(1.0);
+    let alpha = coverage * composited_alpha + (1.0 - coverage) * dst_alpha;
+
+    // computed with premultiplied colors
+    let channel = |src: u8, dst: u8| {
+        let (src, dst) = (unit(src), unit(dst));
+        let mixed = (1.0 - dst_alpha) * src + dst_alpha * blend(dst, src);
+        let composited = (src_alpha * src_factor * mixed + dst_alpha * dst_factor * dst).min(composited_alpha);
+        let color = coverage * composited + (1.0 - coverage) * dst_alpha * dst;
+        match alpha > 0.0 {
+            true => (color / alpha * 255.0 + 0.5) as u8,
+            false => 0,
+        }
+    };
+
+    *dst_pixel = RGBA8::new(
+        channel(src_pixel.r, dst_pixel.r),
+        channel(src_pixel.g, dst_pixel.g),
+        channel(src_pixel.b, dst_pixel.b),
+        (alpha * 255.0 + 0.5) as u8,
+    );
+}
+
 #[inline(always)]
 pub fn blend_pixel(dst_pixel: &mut RGBA8, src_pixel: RGBA8, mask_alpha: u8, alpha_blend_dst: bool) {
     if src_pixel.a == 255 && mask_alpha == 255 {
@@ -0,0 +1,129 @@
+//! Stroking with caps and joins
+//!
+//! Strokes are decomposed into convex pieces (one quad per segment,
+//! plus caps and joins) which the [`Rasterizer`] adds to the shape
+//! being drawn.
+
+use crate::computing::{Couple, Float, LineCap, LineJoin};
+use crate::rasterizing::Rasterizer;
+
+use vek::vec::Vec2;
+
+#[allow(unused_imports)]
+use vek::num_traits::real::Real;
+
+/// Parameters of a stroke, once computed
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StrokeStyle {
+    pub width: Float,
+    pub cap: LineCap,
+    pub join: LineJoin,
+    pub miter_limit: Float,
+}
+
+impl Rasterizer {
+    /// Adds a polyline to the stroke being drawn
+    pub fn stroke<const SSAA: usize>(&mut self, path: &[Couple], closed: bool, style: StrokeStyle, size: Vec2<usize>) {
+        self.resize::<SSAA>(size);
+
+        let mut points = core::mem::take(&mut self.points);
+        points.clear();
+        for point in path {
+            if points.last().is_none_or(|last| last.distance_squared(*point) > Float::EPSILON) {
+                points.push(*point);
+            }
+        }
+
+        let hw = style.width / 2.0;
+        let closed = closed && points.len() > 2;
+        if closed && points.first() == points.last() {
+            points.pop();
+        }
+
+        if hw > 0.0 {
+            match points.len() {
+                0 => (),
+                1 => self.dot::<SSAA>(points[0], hw, style.cap),
+                n => {
+                    let segments = if closed { n } else { n - 1 };
+                    for i in 0..segments {
+                        let (a, b) = (points[i], points[(i + 1) % n]);
+                        let n = normal(a, b) * hw;
+                        self.polygon::<SSAA>(&[a + n, b + n, b - n, a - n]);
+                    }
+
+                    let joins = if closed { 0..n } else { 1..(n - 1) };
+                    for i in joins {
+                        let previous = points[(i + n - 1) % n];
+                        self.join::<SSAA>(previous, points[i], points[(i + 1) % n], hw, style);
+                    }
+
+                    if !closed {
+                        self.cap::<SSAA>(points[1], points[0], hw, style.cap);
+                        self.cap::<SSAA>(points[n - 2], points[n - 1], hw, style.cap);
+                    }
+                },
+            }
+        }
+
+        self.points = points;
+    }
+
+    fn dot<const SSAA: usize>(&mut self, point: Couple, hw: Float, cap: LineCap) {
+        match cap {
+            LineCap::Butt => (),
+            LineCap::Round => self.disc::<SSAA>(point, hw),
+            LineCap::Square => {
+                let (x, y) = (Couple::new(hw, 0.0), Couple::new(0.0, hw));
+                self.polygon::<SSAA>(&[point - x - y, point + x - y, point + x + y, point - x + y]);
+            },
+        }
+    }
+
+    /// Cap at `end`, for a segment coming from `from`
+    fn cap<const SSAA: usize>(&mut self, from: Couple, end: Couple, hw: Float, cap: LineCap) {
+        match cap {
+            LineCap::Butt => (),
+            LineCap::Round => self.disc::<SSAA>(end, hw),
+            LineCap::Square => {
+                let n = normal(from, end) * hw;
+                let d = Couple::new(n.y, -n.x);
+                self.polygon::<SSAA>(&[end + n, end + n + d, end - n + d, end - n]);
+            },
+        }
+    }
+
+    fn join<const SSAA: usize>(&mut self, a: Couple, b: Couple, c: Couple, hw: Float, style: StrokeStyle) {
+        if style.join == LineJoin::Round {
+            return self.disc::<SSAA>(b, hw);
+        }
+
+        let (n0, n1) = (normal(a, b), normal(b, c));
+        let cross = n0.x * n1.y - n0.y * n1.x;
+        if cross.abs() < Float::EPSILON && n0.dot(n1) > 0.0 {
+            // straight line
+            return;
+        }
+
+        // the join fills the gap on the outer side of the turn
+        let outer = if cross > 0.0 { -hw } else { hw };
+        let (n0, n1) = (n0 * outer, n1 * outer);
+
+        let middle = (n0 + n1) / 2.0;
+        let middle_sq = middle.magnitude_squared();
+        let miter_ratio = hw / middle_sq.sqrt();
+
+        if style.join == LineJoin::Miter && miter_ratio <= style.miter_limit {
+            let tip = b + middle * (hw * hw / middle_sq);
+            self.polygon::<SSAA>(&[b, b + n0, tip, b + n1]);
+        } else {
+            self.polygon::<SSAA>(&[b, b + n0, b + n1]);
+        }
+    }
+}
+
+/// Unit vector perpendicular to `a → b`
+fn normal(a: Couple, b: Couple) -> Couple {
+    let d = (b - a).normalized();
+    Couple::new(-d.y, d.x)
+}